@@ -1,13 +1,28 @@
 const builtin = @import("builtin");
 const std = @import("std");
 
-const Lang = enum { lua51, lua52, lua53, lua54 };
+const Lang = enum { lua51, lua52, lua53, lua54, luajit };
+
+/// C libraries compiled into the interpreters and registered in `package.preload`.
+const Bundled = struct {
+    lpeg: bool,
+    lfs: bool,
+};
 
 pub fn build(b: *std.Build) void {
     const target = b.standardTargetOptions(.{});
     const optimize = b.standardOptimizeOption(.{});
+    const in_process = b.option(bool, "in-process", "Build static libraries for the in-process backend") orelse false;
+    const bundled: Bundled = .{
+        .lpeg = b.option(bool, "lpeg", "Bundle LPeg") orelse false,
+        .lfs = b.option(bool, "lfs", "Bundle LuaFileSystem") orelse false,
+    };
+    const checked = b.option(bool, "checked", "Build also the interpreters with API checks and assertions") orelse false;
+
+    const langs = b.option([]const Lang, "lang", "Lua versions to build (default: all)") orelse
+        &[_]Lang{ .lua51, .lua52, .lua53, .lua54, .luajit };
 
-    for ([_]Lang{ .lua51, .lua52, .lua53, .lua54 }) |lang| {
+    for (langs) |lang| {
         const ziglua = b.lazyDependency("ziglua", .{
             .target = target,
             .optimize = optimize,
@@ -23,8 +38,63 @@ pub fn build(b: *std.Build) void {
         if (ziglua) |d| {
             compile_exe.root_module.addImport("ziglua", d.module("ziglua"));
         }
+        addBundled(b, compile_exe, ziglua, bundled);
+        // export the C API to the modules loaded by `require`
+        compile_exe.rdynamic = true;
+        if (target.result.os.tag == .linux) {
+            compile_exe.linkSystemLibrary("dl");
+        }
         b.installArtifact(compile_exe);
 
+        if (checked) {
+            // `shared` is passed only to get another instance of the dependency
+            const ziglua_checked = b.lazyDependency("ziglua", .{
+                .target = target,
+                .optimize = if (optimize == .Debug) .Debug else .ReleaseSafe,
+                .lang = lang,
+                .shared = false,
+            });
+            const checked_exe = b.addExecutable(.{
+                .name = b.fmt("{s}-checked", .{@tagName(lang)}),
+                .root_source_file = b.path("lua-rt/main.zig"),
+                .target = target,
+                .optimize = if (optimize == .Debug) .Debug else .ReleaseSafe,
+            });
+            if (ziglua_checked) |d| {
+                checked_exe.root_module.addImport("ziglua", d.module("ziglua"));
+                addChecks(d.artifact("lua").root_module, lang);
+            }
+            addBundled(b, checked_exe, ziglua_checked, bundled);
+            checked_exe.rdynamic = true;
+            if (target.result.os.tag == .linux) {
+                checked_exe.linkSystemLibrary("dl");
+            }
+            b.installArtifact(checked_exe);
+        }
+
+        if (in_process) {
+            const lib = b.addStaticLibrary(.{
+                .name = "lunest",
+                .root_source_file = b.path("lua-rt/lib.zig"),
+                .target = target,
+                .optimize = optimize,
+            });
+            lib.linkLibC();
+            if (ziglua) |d| {
+                lib.root_module.addImport("ziglua", d.module("ziglua"));
+            }
+            addBundled(b, lib, ziglua, bundled);
+            // one directory per version since the libraries have the same names
+            const dest_dir: std.Build.Step.InstallArtifact.Options.Dir = .{
+                .override = .{ .custom = b.fmt("lib/{s}", .{@tagName(lang)}) },
+            };
+            b.getInstallStep().dependOn(&b.addInstallArtifact(lib, .{ .dest_dir = dest_dir }).step);
+            if (ziglua) |d| {
+                const lua = d.artifact("lua");
+                b.getInstallStep().dependOn(&b.addInstallArtifact(lua, .{ .dest_dir = dest_dir }).step);
+            }
+        }
+
         const run_step = b.step(b.fmt("run-{s}", .{@tagName(lang)}), "Run the app");
         const run_exe = b.addRunArtifact(compile_exe);
         if (b.args) |args| {
@@ -33,3 +103,43 @@ pub fn build(b: *std.Build) void {
         run_step.dependOn(&run_exe.step);
     }
 }
+
+/// Enables the API checks and the internal assertions, whose macros depend on the version.
+fn addChecks(lua: *std.Build.Module, lang: Lang) void {
+    lua.addCMacro("LUA_USE_APICHECK", "1");
+    switch (lang) {
+        // `llimits.h` keeps `lua_assert` if it's already defined
+        .lua51, .lua52, .lua53 => lua.addCMacro("lua_assert(c)", "assert(c)"),
+        .lua54 => lua.addCMacro("LUAI_ASSERT", "1"),
+        .luajit => lua.addCMacro("LUA_USE_ASSERT", "1"),
+    }
+}
+
+fn addBundled(b: *std.Build, compile: *std.Build.Step.Compile, ziglua: ?*std.Build.Dependency, bundled: Bundled) void {
+    const options = b.addOptions();
+    options.addOption(bool, "lpeg", bundled.lpeg);
+    options.addOption(bool, "lfs", bundled.lfs);
+    compile.root_module.addOptions("bundled", options);
+
+    // for the headers
+    if (ziglua) |d| {
+        compile.linkLibrary(d.artifact("lua"));
+    }
+    if (bundled.lpeg) {
+        if (b.lazyDependency("lpeg", .{})) |d| {
+            compile.addCSourceFiles(.{
+                .root = d.path(""),
+                .files = &.{ "lpcap.c", "lpcode.c", "lpcset.c", "lpprint.c", "lptree.c", "lpvm.c" },
+            });
+        }
+    }
+    if (bundled.lfs) {
+        if (b.lazyDependency("lfs", .{})) |d| {
+            compile.addIncludePath(d.path("src"));
+            compile.addCSourceFiles(.{
+                .root = d.path("src"),
+                .files = &.{"lfs.c"},
+            });
+        }
+    }
+}
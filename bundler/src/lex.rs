@@ -0,0 +1,138 @@
+/// Token of a chunk. Comments and whitespace are skipped.
+#[derive(Debug, PartialEq)]
+pub struct Token<'a> {
+    pub kind: Kind<'a>,
+    /// Byte range in the chunk
+    pub range: std::ops::Range<usize>,
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Kind<'a> {
+    Name(&'a str),
+    /// String literal, with its contents if it has no escape sequences
+    String(Option<&'a str>),
+    /// Any other character, including the ones of numbers and operators
+    Other(u8),
+}
+
+pub fn tokenize(chunk: &str) -> Vec<Token<'_>> {
+    let bytes = chunk.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let kind = match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = match long_bracket(bytes, i + 2) {
+                    Some((_, end)) => end,
+                    None => bytes[i..]
+                        .iter()
+                        .position(|&c| c == b'\n')
+                        .map_or(bytes.len(), |n| i + n),
+                };
+                None
+            }
+            b'"' | b'\'' => {
+                let (contents, end) = quoted(bytes, i);
+                i = end;
+                let escaped = chunk[contents.clone()].contains('\\');
+                Some(Kind::String((!escaped).then(|| &chunk[contents])))
+            }
+            b'[' if long_bracket(bytes, i).is_some() => {
+                let (contents, end) = long_bracket(bytes, i).unwrap();
+                i = end;
+                let contents = &chunk[contents];
+                Some(Kind::String(Some(
+                    contents.strip_prefix('\n').unwrap_or(contents),
+                )))
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                Some(Kind::Name(&chunk[start..i]))
+            }
+            c => {
+                i += 1;
+                (!c.is_ascii_whitespace()).then_some(Kind::Other(c))
+            }
+        };
+        if let Some(kind) = kind {
+            tokens.push(Token {
+                kind,
+                range: start..i,
+                line,
+            });
+        }
+        line += bytes[start..i].iter().filter(|&&c| c == b'\n').count();
+    }
+    tokens
+}
+
+/// Returns the range of the contents of the quoted string at `bytes[i]` and the index after it.
+fn quoted(bytes: &[u8], i: usize) -> (std::ops::Range<usize>, usize) {
+    let quote = bytes[i];
+    let mut j = i + 1;
+    while j < bytes.len() && bytes[j] != quote && bytes[j] != b'\n' {
+        j += if bytes[j] == b'\\' { 2 } else { 1 };
+    }
+    let j = j.min(bytes.len());
+    (i + 1..j, (j + 1).min(bytes.len()))
+}
+
+/// Returns the range of the contents of the long bracket at `bytes[i]` and the index after it.
+fn long_bracket(bytes: &[u8], i: usize) -> Option<(std::ops::Range<usize>, usize)> {
+    if bytes.get(i) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[i + 1..].iter().take_while(|&&c| c == b'=').count();
+    let open = i + 1 + level;
+    if bytes.get(open) != Some(&b'[') {
+        return None;
+    }
+    let close = [b"]".as_slice(), &b"=".repeat(level), b"]"].concat();
+    let start = open + 1;
+    match bytes[start..]
+        .windows(close.len())
+        .position(|w| w == close.as_slice())
+    {
+        Some(n) => Some((start..start + n, start + n + close.len())),
+        None => Some((start..bytes.len(), bytes.len())),
+    }
+}
+
+#[cfg(test)]
+mod tokenize_tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case("a.b", vec![Kind::Name("a"), Kind::Other(b'.'), Kind::Name("b")])]
+    #[case("-- a\n--[[\nb]] c", vec![Kind::Name("c")])]
+    #[case(r#"'a' "b\"" [[c]]"#, vec![
+        Kind::String(Some("a")),
+        Kind::String(None),
+        Kind::String(Some("c")),
+    ])]
+    #[case("[==[\n]]\n]==] t[1]", vec![
+        Kind::String(Some("]]\n")),
+        Kind::Name("t"),
+        Kind::Other(b'['),
+        Kind::Other(b'1'),
+        Kind::Other(b']'),
+    ])]
+    fn kinds(#[case] chunk: &str, #[case] expected: Vec<Kind>) {
+        let kinds: Vec<_> = tokenize(chunk).into_iter().map(|t| t.kind).collect();
+        assert_eq!(expected, kinds);
+    }
+
+    #[test]
+    fn lines() {
+        let lines: Vec<_> = tokenize("a\n[[\n]] b\n-- c\nd")
+            .into_iter()
+            .map(|t| t.line)
+            .collect();
+        assert_eq!(vec![1, 2, 3, 5], lines);
+    }
+}
@@ -1,9 +1,47 @@
-use std::{ffi::OsStr, path::Path};
+mod lex;
+mod require;
+mod strip;
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
 
 #[derive(Default)]
 pub struct Bundler {
-    modules: Vec<Module>,
+    /// Sorted by the names, so that the output is reproducible
+    modules: BTreeMap<String, Module>,
     publics: Vec<String>,
+    unresolved: Vec<Unresolved>,
+    strip_tests: bool,
+}
+
+/// Call of `require` that [`Bundler::add_required_modules`] couldn't follow.
+#[derive(Debug, PartialEq)]
+pub enum Unresolved {
+    /// Argument isn't a string literal
+    Dynamic { module: String, line: usize },
+    /// Module isn't in the search directories
+    NotFound {
+        module: String,
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unresolved::Dynamic { module, line } => {
+                write!(f, "{module}:{line}: module name is not a string literal")
+            }
+            Unresolved::NotFound { module, line, name } => {
+                write!(f, "{module}:{line}: module '{name}' not found")
+            }
+        }
+    }
 }
 
 impl Bundler {
@@ -24,8 +62,7 @@ impl Bundler {
             module_root_file.with_extension("")
         };
 
-        self.modules
-            .push(Module::new(module_dir.parent().unwrap(), module_root_file)?);
+        self.add_module(self.new_module(module_dir.parent().unwrap(), module_root_file)?);
         if module_dir.exists() {
             #[cfg(feature = "build-script")]
             println!("cargo::rerun-if-changed={}", module_dir.display());
@@ -36,8 +73,7 @@ impl Bundler {
                     && path.extension() == Some(OsStr::new("lua"))
                     && path != module_root_file
                 {
-                    self.modules
-                        .push(Module::new(module_dir.parent().unwrap(), entry.path())?);
+                    self.add_module(self.new_module(module_dir.parent().unwrap(), entry.path())?);
                 }
             }
         }
@@ -45,35 +81,146 @@ impl Bundler {
         Ok(self)
     }
 
+    /// Adds the module `entry` and the modules it requires directly or indirectly, found in
+    /// `search_dirs` as `?.lua` or `?/init.lua`.
+    /// Calls of `require` that can't be followed are collected in [`Bundler::unresolved`].
+    pub fn add_required_modules(
+        &mut self,
+        entry: &str,
+        search_dirs: &[impl AsRef<Path>],
+    ) -> std::io::Result<&mut Self> {
+        let mut stack = vec![(entry.to_string(), None)];
+        while let Some((name, required_at)) = stack.pop() {
+            if self.modules.contains_key(&name) {
+                continue;
+            }
+            let Some((base_dir, path)) = find_module(&name, search_dirs) else {
+                match required_at {
+                    Some((module, line)) => {
+                        self.unresolved
+                            .push(Unresolved::NotFound { module, line, name });
+                    }
+                    None => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("module '{name}' not found"),
+                        ));
+                    }
+                }
+                continue;
+            };
+            #[cfg(feature = "build-script")]
+            println!("cargo::rerun-if-changed={}", path.display());
+
+            let module = self.new_module(&base_dir, &path)?;
+            // reversed to visit the modules in the order they are required
+            for (line, require) in require::find(&module.chunk).into_iter().rev() {
+                match require {
+                    require::Require::Static(dep) => {
+                        stack.push((dep, Some((module.name.clone(), line))));
+                    }
+                    require::Require::Dynamic => self.unresolved.push(Unresolved::Dynamic {
+                        module: module.name.clone(),
+                        line,
+                    }),
+                }
+            }
+            self.add_module(module);
+        }
+        Ok(self)
+    }
+
+    /// Calls of `require` found by [`Bundler::add_required_modules`] but not followed.
+    pub fn unresolved(&self) -> &[Unresolved] {
+        &self.unresolved
+    }
+
+    /// Removes in-source tests from the modules added after this call.
+    pub fn strip_tests(&mut self) -> &mut Self {
+        self.strip_tests = true;
+        self
+    }
+
+    fn new_module(&self, base_dir: &Path, path: &Path) -> std::io::Result<Module> {
+        let mut module = Module::new(base_dir, path)?;
+        if self.strip_tests {
+            module.chunk = strip::tests(&module.chunk);
+        }
+        Ok(module)
+    }
+
+    fn add_module(&mut self, module: Module) {
+        self.modules.insert(module.name.clone(), module);
+    }
+
     pub fn make_public(&mut self, module_name: impl Into<String>) -> &mut Self {
         self.publics.push(module_name.into());
         self
     }
 
     pub fn bundle(&self, default_module: Option<impl AsRef<str>>) -> String {
-        let mut result = include_str!("./override.lua").replace(
+        let mut result = self.header();
+        for module in self.modules.values() {
+            result += &module.setup_loader();
+        }
+        if let Some(m) = default_module {
+            result += &format!("return require({})\n", lua_string(m.as_ref()));
+        }
+        result
+    }
+
+    /// Returns a Lua script that writes the bundle precompiled to the bytecode of the interpreter
+    /// running it. Its arguments are the output path, and the path the bundle will be run from if
+    /// it differs, which is used as the chunk name.
+    pub fn precompiler(&self, default_module: Option<impl AsRef<str>>) -> String {
+        let modules: Vec<_> = self
+            .modules
+            .values()
+            .map(|m| {
+                let chunk = long_string(&m.chunk);
+                let name = lua_string(&m.name);
+                let chunk_name = lua_string(&m.chunk_name);
+                format!("{{ {name}, {chunk_name}, {chunk} }}")
+            })
+            .collect();
+        include_str!("./precompile.lua")
+            .replace(
+                "--[[@replace = bundler.HEADER]]",
+                &format!("= {}", long_string(&self.header())),
+            )
+            .replace(
+                "--[[@replace = bundler.MODULES]]",
+                &format!("= {{\n{}\n}}", modules.join(",\n")),
+            )
+            .replace(
+                "--[[@replace = bundler.DEFAULT_MODULE]]",
+                &match default_module {
+                    Some(m) => format!("= {}", lua_string(m.as_ref())),
+                    None => "= nil".into(),
+                },
+            )
+    }
+
+    /// Code defining the private `package` and `require` before the modules.
+    fn header(&self) -> String {
+        include_str!("./override.lua").replace(
             "--[[@replace = bundler.PUBLIC_MODULES]]",
             &format!(
                 "= {{ {} }}",
                 self.publics
                     .iter()
-                    .map(|m| format!("['{m}'] = true"))
+                    .map(|m| format!("[{}] = true", lua_string(m)))
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
-        );
-        for module in &self.modules {
-            result += &module.setup_loader();
-        }
-        if let Some(m) = default_module {
-            result += &format!("return require('{}')\n", m.as_ref());
-        }
-        result
+        )
     }
 }
 
 struct Module {
     name: String,
+    /// Name shown in tracebacks, e.g. `@module/lunest/Test.lua`
+    chunk_name: String,
     chunk: String,
 }
 
@@ -89,6 +236,11 @@ impl Module {
                 }
                 .replace(['/', '\\'], ".")
             },
+            chunk_name: {
+                let root = base_dir.parent().unwrap_or(base_dir);
+                let path = path.strip_prefix(root).unwrap();
+                format!("@{}", path.display()).replace('\\', "/")
+            },
             chunk: match std::fs::read_to_string(path) {
                 Err(e) => Err(std::io::Error::new(
                     e.kind(),
@@ -100,8 +252,165 @@ impl Module {
     }
 
     fn setup_loader(&self) -> String {
-        let modname = &self.name;
-        let chunk = self.chunk.trim();
-        format!("package.preload['{modname}'] = function(...)\n{chunk}\nend\n")
+        let modname = lua_string(&self.name);
+        let chunk_name = lua_string(&self.chunk_name);
+        let chunk = long_string(&self.chunk);
+        // the call is parenthesized to be not a tail call, so that the caller of the chunk is on
+        // the stack
+        format!(
+            "package.preload[{modname}] = function(...)\n\
+             return ((load_module({chunk}, {chunk_name}))(...))\n\
+             end\n"
+        )
+    }
+}
+
+fn find_module(name: &str, search_dirs: &[impl AsRef<Path>]) -> Option<(PathBuf, PathBuf)> {
+    let rel = name.replace('.', "/");
+    for dir in search_dirs {
+        let dir = dir.as_ref();
+        for path in [
+            dir.join(format!("{rel}.lua")),
+            dir.join(&rel).join("init.lua"),
+        ] {
+            if path.is_file() {
+                return Some((dir.to_path_buf(), path));
+            }
+        }
+    }
+    None
+}
+
+/// Quotes `s` as a long bracket string literal.
+fn long_string(s: &str) -> String {
+    let mut level = String::new();
+    while s.contains(&format!("]{level}]")) {
+        level.push('=');
+    }
+    // the first newline is skipped, so that the line numbers stay the same
+    format!("[{level}[\n{s}\n]{level}]")
+}
+
+/// Quotes `s` as a single-quoted string literal.
+fn lua_string(s: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in s.chars() {
+        match c {
+            '\'' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            // three digits, so that a following digit isn't part of the escape
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod lua_string_tests {
+    #[rstest::rstest]
+    #[case("lunest.Test", "'lunest.Test'")]
+    #[case("it's", r"'it\'s'")]
+    #[case(r"a\b", r"'a\\b'")]
+    #[case("a\nb\r", r"'a\nb\r'")]
+    #[case("\x001", r"'\0001'")]
+    fn lua_string(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(expected, super::lua_string(input));
+    }
+}
+
+#[cfg(test)]
+mod long_string_tests {
+    #[rstest::rstest]
+    #[case("return 1", "[[\nreturn 1\n]]")]
+    #[case("t[a[1]]", "[=[\nt[a[1]]\n]=]")]
+    #[case("x = [[]]..[=[]=]", "[==[\nx = [[]]..[=[]=]\n]==]")]
+    fn long_string(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(expected, super::long_string(input));
+    }
+}
+
+#[cfg(test)]
+mod add_required_modules_tests {
+    use super::*;
+
+    #[test]
+    fn add_required_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "main.lua",
+                "local a = require('a')\nlocal lib = require(\"lib\")",
+            ),
+            ("a/init.lua", "return require('a.b')"),
+            ("a/b.lua", "return require(name)"),
+            ("lib.lua", "require('a')\nrequire('missing')"),
+            ("unused.lua", "return 1"),
+        ];
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let mut bundler = Bundler::new();
+        bundler.add_required_modules("main", &[dir.path()]).unwrap();
+        assert_eq!(
+            ["a", "a.b", "lib", "main"],
+            bundler.modules.keys().collect::<Vec<_>>().as_slice(),
+        );
+        assert_eq!(
+            [
+                Unresolved::Dynamic {
+                    module: "a.b".into(),
+                    line: 1,
+                },
+                Unresolved::NotFound {
+                    module: "lib".into(),
+                    line: 2,
+                    name: "missing".into(),
+                },
+            ],
+            bundler.unresolved(),
+        );
+        assert!(
+            Bundler::new()
+                .add_required_modules("missing", &[dir.path()])
+                .is_err()
+        );
+    }
+}
+
+#[cfg(test)]
+mod precompiler_tests {
+    use super::*;
+
+    #[test]
+    fn precompiler() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.lua"), "return 1").unwrap();
+        let mut bundler = Bundler::new();
+        bundler.add_required_modules("main", &[dir.path()]).unwrap();
+        let script = bundler.precompiler(Some("main"));
+        assert!(!script.contains("@replace"));
+        assert!(script.contains("local DEFAULT_MODULE = 'main'"));
+        assert!(script.contains("{ 'main', '@"));
+    }
+
+    #[test]
+    fn quotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().join("it's");
+        std::fs::create_dir(&base_dir).unwrap();
+        std::fs::write(base_dir.join("main.lua"), "return 1").unwrap();
+        let mut bundler = Bundler::new();
+        bundler.add_required_modules("main", &[&base_dir]).unwrap();
+        let script = bundler.precompiler(Some("main"));
+        assert!(script.contains(r"{ 'main', '@it\'s/main.lua', "));
     }
 }
@@ -69,3 +69,19 @@ local function require(modname) ---@diagnostic disable-line: unused-function, un
 
     return _G.require(modname)
 end
+
+-- globals of the bundled modules, which see the overridden functions above
+local module_env = setmetatable({ package = package, require = require, pairs = pairs }, {
+    __index = _G,
+    __newindex = _G,
+})
+
+---@param chunk string
+---@param chunkname string
+---@return function
+local function load_module(chunk, chunkname) ---@diagnostic disable-line: unused-function, unused-local
+    if setfenv then
+        return setfenv(assert(loadstring(chunk, chunkname)), module_env)
+    end
+    return assert(load(chunk, chunkname, "bt", module_env))
+end
@@ -0,0 +1,38 @@
+-- Writes the bundle precompiled by the running interpreter.
+-- Usage: precompile.lua OUTPUT [SCRIPT], where SCRIPT is the path the bundle will be run from.
+local output = assert(arg[1], "output path is not specified")
+local script = arg[2] or output
+
+local load = _VERSION == "Lua 5.1" and loadstring or load
+
+---@type string
+local HEADER --[[@replace = bundler.HEADER]]
+
+--- Name, chunk name and source of each module
+---@type string[][]
+local MODULES --[[@replace = bundler.MODULES]]
+
+---@type string?
+local DEFAULT_MODULE --[[@replace = bundler.DEFAULT_MODULE]]
+
+local parts = { HEADER }
+for _, module in ipairs(MODULES) do
+    local name, chunkname, chunk = module[1], module[2], module[3]
+    local func = assert(load(chunk, chunkname))
+    table.insert(
+        parts,
+        ("package.preload[%q] = function(...)\nreturn ((load_module(%q, %q))(...))\nend\n"):format(
+            name,
+            string.dump(func),
+            chunkname
+        )
+    )
+end
+if DEFAULT_MODULE then
+    table.insert(parts, ("return require(%q)\n"):format(DEFAULT_MODULE))
+end
+
+local main = assert(load(table.concat(parts), "@" .. script))
+local file = assert(io.open(output, "wb"))
+assert(file:write(string.dump(main)))
+assert(file:close())
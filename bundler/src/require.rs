@@ -0,0 +1,58 @@
+use crate::lex::{self, Kind};
+
+/// Argument of a call of `require` found in a chunk.
+#[derive(Debug, PartialEq)]
+pub enum Require {
+    /// String literal
+    Static(String),
+    /// Any other expression
+    Dynamic,
+}
+
+/// Finds the calls of `require` in `chunk` with their line numbers.
+pub fn find(chunk: &str) -> Vec<(usize, Require)> {
+    let tokens = lex::tokenize(chunk);
+    let mut requires = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != Kind::Name("require")
+            || i > 0 && matches!(tokens[i - 1].kind, Kind::Other(b'.' | b':'))
+        {
+            continue;
+        }
+        let kind = |n: usize| tokens.get(i + n).map(|t| &t.kind);
+        let require = match (kind(1), kind(2), kind(3)) {
+            (Some(Kind::String(Some(name))), _, _) => Require::Static(name.to_string()),
+            (Some(Kind::String(None)), _, _) => Require::Dynamic,
+            (Some(Kind::Other(b'(')), Some(Kind::String(Some(name))), Some(Kind::Other(b')'))) => {
+                Require::Static(name.to_string())
+            }
+            (Some(Kind::Other(b'(')), _, _) => Require::Dynamic,
+            _ => continue,
+        };
+        requires.push((token.line, require));
+    }
+    requires
+}
+
+#[cfg(test)]
+mod find_tests {
+    use super::*;
+
+    fn s(name: &str) -> Require {
+        Require::Static(name.into())
+    }
+
+    #[rstest::rstest]
+    #[case(r#"local a = require("a")"#, vec![(1, s("a"))])]
+    #[case("local a = require 'a.b'\nrequire [[c]]", vec![(1, s("a.b")), (2, s("c"))])]
+    #[case("require ( \"a\" )", vec![(1, s("a"))])]
+    #[case("require(name)\nrequire('a' .. b)", vec![(1, Require::Dynamic), (2, Require::Dynamic)])]
+    #[case(r#"require("a\\b")"#, vec![(1, Require::Dynamic)])]
+    #[case("local require = require\nlocal x = t.require('a')", vec![])]
+    #[case("-- require('a')\n--[[\nrequire('b')\n]] require('c')", vec![(4, s("c"))])]
+    #[case("local s = \"require('a')\" .. [==[\n]]\n]==]\nrequire('b')", vec![(4, s("b"))])]
+    #[case("local my_require = 1; foo:require('a')", vec![])]
+    fn find(#[case] chunk: &str, #[case] expected: Vec<(usize, Require)>) {
+        assert_eq!(expected, super::find(chunk));
+    }
+}
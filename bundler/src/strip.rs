@@ -0,0 +1,116 @@
+use crate::lex::{self, Kind, Token};
+
+const LUNEST_MODULES: [&str; 2] = ["lunest", "lunest.wrapper"];
+
+/// Removes the calls of `test` and `group` of lunest from `chunk`, and replaces `require()` of
+/// lunest with an empty table. Removed lines are kept empty, so that the line numbers stay the same.
+pub fn tests(chunk: &str) -> String {
+    let tokens = lex::tokenize(chunk);
+    let mut names = Vec::new();
+    let mut edits = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let kind = |n: usize| tokens.get(i + n).map(|t| &t.kind);
+        match token.kind {
+            // local test = require("lunest")
+            Kind::Name("local") => {
+                let (Some(Kind::Name(name)), Some(Kind::Other(b'=')), Some(Kind::Name("require"))) =
+                    (kind(1), kind(2), kind(3))
+                else {
+                    continue;
+                };
+                let (arg, end) = match (kind(4), kind(5), kind(6)) {
+                    (Some(Kind::String(Some(arg))), _, _) => (arg, i + 4),
+                    (
+                        Some(Kind::Other(b'(')),
+                        Some(Kind::String(Some(arg))),
+                        Some(Kind::Other(b')')),
+                    ) => (arg, i + 6),
+                    _ => continue,
+                };
+                if LUNEST_MODULES.contains(arg) {
+                    names.push(*name);
+                    edits.push((
+                        tokens[i + 3].range.start..tokens[end].range.end,
+                        "{}".into(),
+                    ));
+                }
+            }
+            // test.test("name", function() ... end)
+            Kind::Name(name) if names.contains(&name) => {
+                let (
+                    Some(Kind::Other(b'.')),
+                    Some(Kind::Name("test" | "group")),
+                    Some(Kind::Other(b'(')),
+                ) = (kind(1), kind(2), kind(3))
+                else {
+                    continue;
+                };
+                let Some(end) = closing_paren(&tokens[i + 3..]) else {
+                    continue;
+                };
+                let range = token.range.start..tokens[i + 3 + end].range.end;
+                let lines = "\n".repeat(chunk[range.clone()].matches('\n').count());
+                edits.push((range, lines));
+            }
+            _ => {}
+        }
+    }
+
+    let mut result = String::with_capacity(chunk.len());
+    let mut pos = 0;
+    for (range, replacement) in edits {
+        // skip the ones inside removed calls
+        if range.start < pos {
+            continue;
+        }
+        result.push_str(&chunk[pos..range.start]);
+        result.push_str(&replacement);
+        pos = range.end;
+    }
+    result.push_str(&chunk[pos..]);
+    result
+}
+
+/// Returns the index of the parenthesis closing `tokens[0]`.
+fn closing_paren(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            Kind::Other(b'(') => depth += 1,
+            Kind::Other(b')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests_tests {
+    #[rstest::rstest]
+    #[case("local M = {}\nreturn M", "local M = {}\nreturn M")]
+    #[case(
+        "local test = require(\"lunest\")\nlocal a = test.assertion\n\
+         test.test(\"a\", function()\n    a.eq(1, f(1))\nend)\nreturn M",
+        "local test = {}\nlocal a = test.assertion\n\n\n\nreturn M"
+    )]
+    #[case(
+        "local t = require 'lunest.wrapper'\nt.group('g', function()\n    t.test('a', function() end)\nend)",
+        "local t = {}\n\n\n"
+    )]
+    #[case(
+        "local t = require('other')\nt.test('a', function() end)",
+        "local t = require('other')\nt.test('a', function() end)"
+    )]
+    #[case(
+        "local t = require('lunest')\nt.test(')', function() -- )\nend)\nx()",
+        "local t = {}\n\n\nx()"
+    )]
+    fn tests(#[case] chunk: &str, #[case] expected: &str) {
+        assert_eq!(expected, super::tests(chunk));
+    }
+}
@@ -1,11 +1,23 @@
+#[path = "../lua-rt/hash.rs"]
+mod hash;
+
 fn main() -> std::io::Result<()> {
     let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    let mut bundler = bundler::Bundler::new();
+    bundler
+        .add_modules("../module/lunest.lua")?
+        .add_modules("../3rd/json.lua/json.lua")?
+        .make_public("lunest");
+    let main = bundler.bundle(Some("lunest"));
+    let precompiler = bundler.precompiler(Some("lunest"));
+    // stable across builds, unlike `std::hash::DefaultHasher`, to name the precompiled scripts
     std::fs::write(
-        out_dir.join("main.lua"),
-        bundler::Bundler::new()
-            .add_modules("../module/lunest.lua")?
-            .add_modules("../3rd/json.lua/json.lua")?
-            .make_public("lunest")
-            .bundle(Some("lunest")),
-    )
+        out_dir.join("main_hash.rs"),
+        format!(
+            "{:#x}",
+            hash::fnv1a(&[main.as_bytes(), precompiler.as_bytes()].concat())
+        ),
+    )?;
+    std::fs::write(out_dir.join("main.lua"), main)?;
+    std::fs::write(out_dir.join("precompile.lua"), precompiler)
 }
@@ -4,25 +4,68 @@ use merge::Merge as _;
 pub struct App {
     root_dir: std::path::PathBuf,
     profiles: Vec<crate::profile::Profile>,
+    targets: Vec<Target>,
 
     temp_dir: tempfile::TempDir,
     main_script: std::path::PathBuf,
+    use_cache: bool,
     program_cache:
         std::cell::RefCell<std::collections::HashMap<std::ffi::OsString, std::ffi::OsString>>,
+    /// Main scripts precompiled by the embedded interpreters
+    precompiled_scripts:
+        std::cell::RefCell<std::collections::HashMap<std::ffi::OsString, std::path::PathBuf>>,
     process_dir_counter: std::cell::Cell<usize>,
 }
 
 #[derive(clap::Args, Debug)]
 pub struct Options {
+    /// Load only the specified files or directories, or the tests defined at the specified lines
+    #[arg(value_name = "PATH[:LINE]")]
+    targets: Vec<String>,
     /// Load Lua files with the specified profile
     #[arg(long, short, value_delimiter = ',')]
     profile: Vec<String>,
     /// Load Lua files with the profiles in the specified group
     #[arg(long, short, value_delimiter = ',')]
     group: Vec<String>,
+    /// Override the command to run Lua files (e.g. "lua5.1", "nvim -l")
+    #[arg(long, value_name = "COMMAND")]
+    lua: Option<String>,
+    /// Override the glob patterns of the files to load, relative to the root directory
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Override the glob patterns of the files not to load, relative to the root directory
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Override the Lua script executed before loading files
+    #[arg(long, value_name = "FILE")]
+    init: Option<std::path::PathBuf>,
     /// Don't clean up a temporary directory on exit
     #[arg(long)]
     keep_tmpdir: bool,
+    /// Don't reuse the embedded Lua interpreters extracted by previous runs
+    #[arg(long)]
+    no_cache: bool,
+}
+
+impl Options {
+    /// Returns the settings that take precedence over the ones of every profile.
+    fn overrides(&self) -> anyhow::Result<crate::profile::Specifier> {
+        let spanned = |v: &[String]| {
+            (!v.is_empty()).then(|| {
+                v.iter()
+                    .map(|s| toml::Spanned::new(0..0, s.clone()))
+                    .collect()
+            })
+        };
+        Ok(crate::profile::Specifier {
+            lua: self.lua.as_deref().map(split_command).transpose()?,
+            include: spanned(&self.include),
+            exclude: spanned(&self.exclude),
+            init: self.init.as_deref().map(std::path::absolute).transpose()?,
+            ..crate::profile::Specifier::empty()
+        })
+    }
 }
 
 impl App {
@@ -30,29 +73,61 @@ impl App {
         log::trace!("creating new app context");
 
         let (root_dir, config_file) = find_config_file(std::env::current_dir()?);
-        let spec: Specifier = if let Some(path) = config_file {
-            toml::from_str(&std::fs::read_to_string(path)?)?
+        let config = config_file.map(crate::config::File::read).transpose()?;
+        let spec: Specifier = if let Some(config) = &config {
+            config.parse()?
         } else {
-            Specifier::default()
+            Specifier::ad_hoc()
         };
-        let profiles = spec.profiles(&root_dir, opts.profile, opts.group)?;
+        let overrides = opts.overrides()?;
+        let mut profiles = spec
+            .profiles(&root_dir, opts.profile, opts.group, &overrides)
+            .map_err(|e| match &config {
+                Some(config) => config.locate(e),
+                None => e,
+            })?;
         assert!(!profiles.is_empty());
 
+        let targets = opts
+            .targets
+            .iter()
+            .map(|s| Target::parse(s))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !targets.is_empty() {
+            for profile in &mut profiles {
+                profile.retain_target_files(|path| {
+                    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+                    targets.iter().any(|t| t.contains_file(&path))
+                });
+            }
+            for target in &targets {
+                anyhow::ensure!(
+                    profiles.iter().any(|p| p
+                        .target_files()
+                        .iter()
+                        .any(|f| std::fs::canonicalize(f).is_ok_and(|f| target.contains_file(&f)))),
+                    "{:?} is not a target file of the selected profiles",
+                    target.path,
+                );
+            }
+        }
+
         let temp_dir = tempfile::Builder::new()
             .prefix(env!("CARGO_PKG_NAME"))
             .disable_cleanup(opts.keep_tmpdir)
             .tempdir()?;
         let main_script = temp_dir.path().join("main.lua");
-        std::fs::write(
-            &main_script,
-            include_str!(concat!(env!("OUT_DIR"), "/main.lua")),
-        )?;
+        std::fs::write(&main_script, MAIN_SCRIPT)?;
+        std::fs::write(temp_dir.path().join("precompile.lua"), PRECOMPILER)?;
         Ok(Self {
             root_dir,
             profiles,
+            targets,
             temp_dir,
             main_script,
+            use_cache: !opts.no_cache,
             program_cache: std::cell::RefCell::new(std::collections::HashMap::new()),
+            precompiled_scripts: std::cell::RefCell::new(std::collections::HashMap::new()),
             process_dir_counter: std::cell::Cell::new(0),
         })
     }
@@ -65,6 +140,16 @@ impl App {
         &self.profiles
     }
 
+    /// Returns `true` if the test is selected by the command-line arguments, where `tests` are
+    /// all tests found by the profile.
+    pub fn is_test_selected(
+        &self,
+        info: &crate::process::TestInfo,
+        tests: &[crate::process::TestInfo],
+    ) -> bool {
+        is_test_selected(&self.targets, info, tests)
+    }
+
     pub fn create_process_dir(&self) -> std::io::Result<std::path::PathBuf> {
         let counter = self.process_dir_counter.get();
         let name = format!("p{counter:x}");
@@ -74,10 +159,21 @@ impl App {
         Ok(dir)
     }
 
+    /// Returns the main script as source, since the in-process backend doesn't use the
+    /// precompiled ones of [`App::get_main_script_for`].
+    #[cfg(feature = "in-process")]
     pub fn get_main_script(&self) -> &std::path::Path {
         &self.main_script
     }
 
+    /// Returns the main script precompiled for `program` if it's an embedded interpreter.
+    pub fn get_main_script_for(&self, program: &std::ffi::OsStr) -> std::path::PathBuf {
+        match self.precompiled_scripts.borrow().get(program) {
+            Some(path) => path.clone(),
+            None => self.main_script.clone(),
+        }
+    }
+
     pub fn get_lua_program(
         &self,
         name: impl AsRef<std::ffi::OsStr>,
@@ -89,9 +185,27 @@ impl App {
         let program: std::ffi::OsString = if let Ok(path) = which::which(name) {
             path.into()
         } else if let Some(lua) = lua_rt::Lua::from_program_name(name) {
-            let path = self.temp_dir.path().join(lua.recommended_program_name());
-            lua.write(&path)?;
+            let path = crate::cache::extract_lua(lua, self.use_cache, self.temp_dir.path())?;
+            let precompiler = self.temp_dir.path().join("precompile.lua");
+            match precompile_main_script(&path, &precompiler) {
+                Ok(script) => {
+                    log::debug!("using precompiled main script: {script:?}");
+                    self.precompiled_scripts
+                        .borrow_mut()
+                        .insert(path.clone().into(), script);
+                }
+                Err(e) => log::warn!("failed to precompile the main script: {e}"),
+            }
             path.into()
+        } else if let Some(features) = lua_rt::Lua::features_for_program_name(name) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} is not installed, and lunest needs {} to embed it",
+                    name.display(),
+                    describe_features(features),
+                ),
+            ));
         } else {
             name.into()
         };
@@ -100,59 +214,212 @@ impl App {
             .insert(name.to_os_string(), program.clone());
         Ok(program)
     }
+
+    /// Returns the embedded interpreter if the profile can run it in this process.
+    #[cfg(feature = "in-process")]
+    pub fn get_in_process_lua(&self, profile: &crate::profile::Profile) -> Option<lua_rt::Lua> {
+        let cmd = profile.lua_command();
+        if !cmd.get_args().is_empty() || cmd.has_env() {
+            return None;
+        }
+        // C modules can't find the API, whose symbols are prefixed
+        if !profile.package_cpath().is_empty() {
+            return None;
+        }
+        // the working directory is shared with the other threads
+        if std::env::current_dir().ok()?.canonicalize().ok()? != profile.cwd() {
+            return None;
+        }
+        if which::which(cmd.get_program()).is_ok() {
+            return None;
+        }
+        lua_rt::Lua::from_program_name(cmd.get_program()).filter(|lua| !lua.is_checked())
+    }
+}
+
+pub(crate) const MAIN_SCRIPT: &str = include_str!(concat!(env!("OUT_DIR"), "/main.lua"));
+const PRECOMPILER: &str = include_str!(concat!(env!("OUT_DIR"), "/precompile.lua"));
+/// FNV-1a hash of [`MAIN_SCRIPT`] and [`PRECOMPILER`]
+const MAIN_SCRIPT_HASH: u64 = include!(concat!(env!("OUT_DIR"), "/main_hash.rs"));
+
+/// Writes the main script precompiled by the interpreter at `program` next to it, unless it
+/// already exists.
+fn precompile_main_script(
+    program: &std::path::Path,
+    precompiler: &std::path::Path,
+) -> std::io::Result<std::path::PathBuf> {
+    let stem = program.file_stem().unwrap_or_default().to_string_lossy();
+    let path = program.with_file_name(format!(
+        "{stem}-main-{}-{MAIN_SCRIPT_HASH:016x}.luac",
+        env!("CARGO_PKG_VERSION"),
+    ));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let temp = path.with_extension(format!("luac.{}", std::process::id()));
+    let out = std::process::Command::new(program)
+        .arg(precompiler)
+        .arg(&temp)
+        .arg(&path)
+        .output()?;
+    if !out.status.success() {
+        _ = std::fs::remove_file(&temp);
+        return Err(std::io::Error::other(format!(
+            "{} exited with {}: {}",
+            program.display(),
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim(),
+        )));
+    }
+    std::fs::rename(&temp, &path)?;
+    Ok(path)
+}
+
+/// Returns e.g. "the 'lua54' and 'checked' features".
+pub fn describe_features(features: &[&str]) -> String {
+    let names: Vec<_> = features.iter().map(|f| format!("'{f}'")).collect();
+    if names.len() == 1 {
+        format!("the {} feature", names[0])
+    } else {
+        format!("the {} features", names.join(" and "))
+    }
+}
+
+/// File or directory specified in the command-line arguments.
+#[derive(Debug, PartialEq)]
+struct Target {
+    path: std::path::PathBuf,
+    line: Option<u32>,
+}
+
+impl Target {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        let (path, line) = match s.rsplit_once(':') {
+            Some((path, line)) if !path.is_empty() => match line.parse() {
+                Ok(n) => (path, Some(n)),
+                Err(_) => (s, None),
+            },
+            _ => (s, None),
+        };
+        Ok(Self {
+            path: std::fs::canonicalize(path)
+                .with_context(|| format!("failed to find {path:?}"))?,
+            line,
+        })
+    }
+
+    fn contains_file(&self, path: &std::path::Path) -> bool {
+        path.starts_with(&self.path)
+    }
+}
+
+/// A line selects the tests in the innermost group or test defined over it, so that a line inside
+/// a test doesn't select the other tests of its group.
+fn is_test_selected(
+    targets: &[Target],
+    info: &crate::process::TestInfo,
+    tests: &[crate::process::TestInfo],
+) -> bool {
+    if targets.is_empty() {
+        return true;
+    }
+    let source = std::fs::canonicalize(&info.source).unwrap_or_else(|_| info.source.clone());
+    targets.iter().any(|t| {
+        if !t.contains_file(&source) {
+            return false;
+        }
+        let Some(line) = t.line else {
+            return true;
+        };
+        // nested ranges are inside the ones of their groups, so the shortest one is innermost
+        let innermost = tests
+            .iter()
+            .filter(|other| other.source == info.source)
+            .flat_map(|other| &other.line_ranges)
+            .filter(|r| r.first <= line && line <= r.last)
+            .min_by_key(|r| r.last - r.first);
+        innermost.is_some_and(|innermost| info.line_ranges.contains(innermost))
+    })
 }
 
 #[derive(Debug, Default, serde::Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 struct Specifier {
-    group: std::collections::HashMap<String, Vec<String>>,
+    group: std::collections::HashMap<String, Vec<toml::Spanned<String>>>,
     profile: std::collections::HashMap<String, crate::profile::Specifier>,
+    matrix: std::collections::HashMap<String, Matrix>,
+}
+
+/// Template expanded into a profile for every combination of the axes.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Matrix {
+    lua: Vec<toml::Spanned<MatrixCommand>>,
+    env: std::collections::BTreeMap<String, Vec<toml::Spanned<String>>>,
+    template: Option<crate::profile::Specifier>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum MatrixCommand {
+    Program(String),
+    Command(Vec<String>),
 }
 
 impl Specifier {
+    /// Returns the specifier used when the configuration file is not found.
+    fn ad_hoc() -> Self {
+        let mut spec = Self::default();
+        spec.profile
+            .insert("default".into(), crate::profile::Specifier::empty());
+        spec
+    }
+
     fn profiles(
         mut self,
         root_dir: &std::path::Path,
-        mut profile_names: Vec<String>,
+        profile_names: Vec<String>,
         group_names: Vec<String>,
+        overrides: &crate::profile::Specifier,
     ) -> anyhow::Result<Vec<crate::profile::Profile>> {
+        self.expand_matrices()?;
+        let mut names = indexmap::IndexSet::new();
+        names.extend(profile_names);
         for group_name in group_names {
-            let group = self
-                .group
-                .get(&group_name)
-                .with_context(|| format!("'{group_name}' group is not defined"))?;
-            for profile_name in group {
-                anyhow::ensure!(
-                    self.profile.contains_key(profile_name),
-                    "'{profile_name}' profile specified in '{group_name}' group is not defined",
-                );
-                profile_names.push(profile_name.clone());
-            }
+            anyhow::ensure!(
+                self.group.contains_key(&group_name),
+                with_suggestion(
+                    format!("'{group_name}' group is not defined"),
+                    &group_name,
+                    self.group.keys(),
+                ),
+            );
+            self.expand_group(&group_name, &mut Vec::new(), &mut names)?;
         }
-        profile_names.dedup();
-        if profile_names.is_empty() {
+        if names.is_empty() {
             if self.profile.contains_key("default") {
-                profile_names.push("default".to_string());
+                names.insert("default".to_string());
             } else if self.profile.len() == 1 {
-                profile_names.push(self.profile.keys().next().unwrap().to_string());
+                names.insert(self.profile.keys().next().unwrap().to_string());
             } else {
                 anyhow::bail!("you must specify the profile or define a 'default' profile");
             }
         }
 
-        let default_profile_spec = if let Some(mut p) = self.profile.get("default").cloned() {
-            p.merge(crate::profile::Specifier::default());
-            p
-        } else {
-            crate::profile::Specifier::default()
-        };
         let mut profiles = Vec::new();
-        for profile_name in profile_names {
-            let mut profile_spec = self
-                .profile
-                .remove(&profile_name)
-                .with_context(|| format!("'{profile_name}' profile is not defined"))?;
-            profile_spec.merge(default_profile_spec.clone());
+        for profile_name in names {
+            anyhow::ensure!(
+                self.profile.contains_key(&profile_name),
+                with_suggestion(
+                    format!("'{profile_name}' profile is not defined"),
+                    &profile_name,
+                    self.profile.keys(),
+                ),
+            );
+            let mut profile_spec = overrides.clone();
+            profile_spec.merge(self.resolve_profile(&profile_name, &mut Vec::new())?);
+            profile_spec.merge(crate::profile::Specifier::default());
             profiles.push(crate::profile::Profile::from_spec(
                 profile_name,
                 profile_spec,
@@ -161,6 +428,252 @@ impl Specifier {
         }
         Ok(profiles)
     }
+
+    /// Adds the profiles generated from the matrices, and the groups named after the matrices.
+    fn expand_matrices(&mut self) -> anyhow::Result<()> {
+        for (matrix_name, matrix) in std::mem::take(&mut self.matrix) {
+            anyhow::ensure!(
+                !self.group.contains_key(&matrix_name),
+                "'{matrix_name}' is defined as both a matrix and a group",
+            );
+            let mut group = Vec::new();
+            for (profile_name, spec) in matrix.expand(&matrix_name)? {
+                if self.profile.contains_key(profile_name.get_ref()) {
+                    return Err(crate::config::SpannedError::new(
+                        profile_name.span(),
+                        format!(
+                            "'{profile_name}' profile generated from '{matrix_name}' matrix is \
+                             already defined"
+                        ),
+                    )
+                    .into());
+                }
+                self.profile.insert(profile_name.get_ref().clone(), spec);
+                group.push(profile_name);
+            }
+            self.group.insert(matrix_name, group);
+        }
+        Ok(())
+    }
+
+    /// Collects the profile names in the group, expanding the nested groups.
+    fn expand_group(
+        &self,
+        group_name: &str,
+        stack: &mut Vec<String>,
+        names: &mut indexmap::IndexSet<String>,
+    ) -> anyhow::Result<()> {
+        stack.push(group_name.to_string());
+        for name in &self.group[group_name] {
+            let is_profile = self.profile.contains_key(name.get_ref());
+            let is_group = self.group.contains_key(name.get_ref());
+            if is_profile && is_group {
+                return Err(crate::config::SpannedError::new(
+                    name.span(),
+                    format!("'{name}' is defined as both a profile and a group"),
+                )
+                .into());
+            } else if is_profile {
+                names.insert(name.get_ref().clone());
+            } else if is_group {
+                if stack.contains(name.get_ref()) {
+                    return Err(crate::config::SpannedError::new(
+                        name.span(),
+                        format!("cyclic group found: {} -> {name}", stack.join(" -> ")),
+                    )
+                    .into());
+                }
+                self.expand_group(name.get_ref(), stack, names)?;
+            } else {
+                return Err(undefined_error(
+                    name,
+                    "profile or group",
+                    self.profile.keys().chain(self.group.keys()),
+                ));
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Returns the profile merged with the profiles it extends and the 'default' profile.
+    fn resolve_profile(
+        &self,
+        profile_name: &str,
+        stack: &mut Vec<String>,
+    ) -> anyhow::Result<crate::profile::Specifier> {
+        stack.push(profile_name.to_string());
+        let mut spec = self.profile[profile_name].clone();
+        if let Some(parent) = spec.extends.take() {
+            if stack.contains(parent.get_ref()) {
+                return Err(crate::config::SpannedError::new(
+                    parent.span(),
+                    format!("cyclic 'extends' found: {} -> {parent}", stack.join(" -> ")),
+                )
+                .into());
+            }
+            if !self.profile.contains_key(parent.get_ref()) {
+                return Err(undefined_error(&parent, "profile", self.profile.keys()));
+            }
+            spec.merge(self.resolve_profile(parent.get_ref(), stack)?);
+        } else if !stack.iter().any(|name| name == "default")
+            && let Some(default) = self.profile.get("default")
+        {
+            spec.merge(default.clone());
+        }
+        stack.pop();
+        Ok(spec)
+    }
+}
+
+/// Entry of an axis of a matrix, with the suffix it adds to the profile names
+type AxisEntry<T> = (toml::Spanned<String>, T);
+
+impl Matrix {
+    /// Returns the generated profile names and their specifiers. The names are spanned with the
+    /// entries of the last axis.
+    fn expand(
+        self,
+        matrix_name: &str,
+    ) -> anyhow::Result<Vec<(toml::Spanned<String>, crate::profile::Specifier)>> {
+        anyhow::ensure!(
+            !self.lua.is_empty() || !self.env.is_empty(),
+            "'{matrix_name}' matrix has no axes",
+        );
+        let template = self
+            .template
+            .unwrap_or_else(crate::profile::Specifier::empty);
+        let mut combinations = vec![(toml::Spanned::new(0..0, matrix_name.to_string()), template)];
+        if !self.lua.is_empty() {
+            let axis: Vec<_> = self
+                .lua
+                .iter()
+                .map(|lua| {
+                    let command = match lua.get_ref() {
+                        MatrixCommand::Program(program) => vec![program.clone()],
+                        MatrixCommand::Command(command) => command.clone(),
+                    };
+                    let suffix = command
+                        .first()
+                        .and_then(|p| std::path::Path::new(p).file_name())
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned();
+                    (toml::Spanned::new(lua.span(), suffix), command)
+                })
+                .collect();
+            check_duplicates(&axis, "lua", matrix_name).map_err(|e| {
+                e.help("profile names are generated from the file names of the programs")
+            })?;
+            combinations = product(combinations, &axis, |spec, lua| {
+                spec.lua = Some(lua.clone());
+            });
+        }
+        for (key, values) in &self.env {
+            anyhow::ensure!(
+                !values.is_empty(),
+                "'env.{key}' axis of '{matrix_name}' matrix is empty",
+            );
+            let axis: Vec<_> = values
+                .iter()
+                .map(|v| (v.clone(), v.get_ref().clone()))
+                .collect();
+            check_duplicates(&axis, &format!("env.{key}"), matrix_name)?;
+            combinations = product(combinations, &axis, |spec, value| {
+                spec.env
+                    .get_or_insert_default()
+                    .insert(key.clone(), value.clone());
+            });
+        }
+        Ok(combinations)
+    }
+}
+
+/// Returns an error for the first entry generating the same profile names as a previous one.
+fn check_duplicates<T>(
+    axis: &[AxisEntry<T>],
+    axis_name: &str,
+    matrix_name: &str,
+) -> Result<(), crate::config::SpannedError> {
+    for (i, (suffix, _)) in axis.iter().enumerate() {
+        if axis[..i]
+            .iter()
+            .any(|(s, _)| s.get_ref() == suffix.get_ref())
+        {
+            return Err(crate::config::SpannedError::new(
+                suffix.span(),
+                format!(
+                    "duplicate entry '{suffix}' in '{axis_name}' axis of '{matrix_name}' matrix"
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn product<T>(
+    combinations: Vec<(toml::Spanned<String>, crate::profile::Specifier)>,
+    axis: &[AxisEntry<T>],
+    apply: impl Fn(&mut crate::profile::Specifier, &T),
+) -> Vec<(toml::Spanned<String>, crate::profile::Specifier)> {
+    let mut r = Vec::with_capacity(combinations.len() * axis.len());
+    for (name, spec) in combinations {
+        for (suffix, value) in axis {
+            let name = toml::Spanned::new(suffix.span(), format!("{name}-{suffix}"));
+            let mut spec = spec.clone();
+            apply(&mut spec, value);
+            r.push((name, spec));
+        }
+    }
+    r
+}
+
+fn undefined_error<'a>(
+    name: &toml::Spanned<String>,
+    kind: &str,
+    candidates: impl IntoIterator<Item = &'a String>,
+) -> anyhow::Error {
+    let mut err =
+        crate::config::SpannedError::new(name.span(), format!("'{name}' {kind} is not defined"));
+    if let Some(s) =
+        crate::config::suggest(name.get_ref(), candidates.into_iter().map(String::as_str))
+    {
+        err = err.help(format!("did you mean '{s}'?"));
+    }
+    err.into()
+}
+
+/// Splits the command line into words, handling single and double quotes.
+fn split_command(s: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    anyhow::ensure!(quote.is_none(), "unclosed quote in {s:?}");
+    words.extend(word);
+    Ok(words)
+}
+
+fn with_suggestion<'a>(
+    message: String,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a String>,
+) -> String {
+    match crate::config::suggest(name, candidates.into_iter().map(String::as_str)) {
+        Some(s) => format!("{message} (did you mean '{s}'?)"),
+        None => message,
+    }
 }
 
 fn find_config_file(cwd: std::path::PathBuf) -> (std::path::PathBuf, Option<std::path::PathBuf>) {
@@ -215,3 +728,356 @@ mod find_config_file_tests {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod specifier_tests {
+    use super::*;
+
+    #[test]
+    fn undefined_profile_in_group() {
+        let config = crate::config::tests::file(
+            "[group]\nall = [\"lua51\", \"lua5\"]\n[profile.lua52]\n[profile.lua51]\n",
+        );
+        let spec: Specifier = config.parse().unwrap();
+        let err = spec
+            .profiles(
+                std::path::Path::new("."),
+                Vec::new(),
+                vec!["all".into()],
+                &crate::profile::Specifier::empty(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            [
+                "'lua5' profile or group is not defined",
+                " --> lunest.toml:2:17",
+                "  |",
+                "2 | all = [\"lua51\", \"lua5\"]",
+                "  |                 ^^^^^^",
+                "  = help: did you mean 'lua51'?",
+            ]
+            .join("\n"),
+            config.locate(err).to_string(),
+        );
+    }
+
+    fn profiles(source: &str, group: &str) -> anyhow::Result<Vec<crate::profile::Profile>> {
+        let root_dir = tempfile::tempdir()?;
+        let spec: Specifier = toml::from_str(source)?;
+        spec.profiles(
+            root_dir.path(),
+            Vec::new(),
+            vec![group.into()],
+            &crate::profile::Specifier::empty(),
+        )
+    }
+
+    #[test]
+    fn extends() -> anyhow::Result<()> {
+        let profiles = profiles(
+            r#"
+            group.all = ["a", "b", "c"]
+            profile.default = { lua = ["lua5.1"], env = { A = "default" } }
+            profile.a = { extends = "b", env = { A = "a" } }
+            profile.b = { extends = "c", lua = ["lua5.2"] }
+            profile.c = { env = { B = "c" } }
+            "#,
+            "all",
+        )?;
+        let lua = |name, a: &str| {
+            crate::command::Builder::new(name)
+                .env("A", a)
+                .env("B", "c")
+                .clone()
+        };
+        assert_eq!(&lua("lua5.2", "a"), profiles[0].lua_command());
+        assert_eq!(&lua("lua5.2", "default"), profiles[1].lua_command());
+        assert_eq!(&lua("lua5.1", "default"), profiles[2].lua_command());
+        Ok(())
+    }
+
+    #[test]
+    fn cyclic_extends() {
+        let err = profiles(
+            r#"
+            group.all = ["a"]
+            profile.a = { extends = "b" }
+            profile.b = { extends = "a" }
+            "#,
+            "all",
+        )
+        .unwrap_err();
+        assert_eq!("cyclic 'extends' found: a -> b -> a", err.to_string());
+    }
+
+    #[test]
+    fn nested_groups() -> anyhow::Result<()> {
+        let profiles = profiles(
+            r#"
+            group.all = ["old", "b", "new"]
+            group.old = ["a", "b"]
+            group.new = ["c"]
+            profile.a = {}
+            profile.b = {}
+            profile.c = {}
+            "#,
+            "all",
+        )?;
+        assert_eq!(
+            vec!["a", "b", "c"],
+            profiles.iter().map(|p| p.name()).collect::<Vec<_>>(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cyclic_groups() {
+        let err = profiles(
+            r#"
+            group.a = ["b"]
+            group.b = ["c", "a"]
+            group.c = []
+            "#,
+            "a",
+        )
+        .unwrap_err();
+        assert_eq!("cyclic group found: a -> b -> a", err.to_string());
+    }
+
+    #[test]
+    fn matrix() -> anyhow::Result<()> {
+        let profiles = profiles(
+            r#"
+            profile.default = { env = { TZ = "UTC" } }
+            [matrix.all]
+            lua = ["lua5.1", ["nvim", "-l"]]
+            env.MODE = ["a", "b"]
+            template = { include = ["test/*.lua"] }
+            "#,
+            "all",
+        )?;
+        assert_eq!(
+            vec!["all-lua5.1-a", "all-lua5.1-b", "all-nvim-a", "all-nvim-b"],
+            profiles.iter().map(|p| p.name()).collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            &crate::command::Builder::new("nvim")
+                .arg("-l")
+                .env("TZ", "UTC")
+                .env("MODE", "b")
+                .clone(),
+            profiles[3].lua_command(),
+        );
+        Ok(())
+    }
+
+    fn located_error(source: &str, group: &str) -> String {
+        let config = crate::config::tests::file(source);
+        let spec: Specifier = config.parse().unwrap();
+        let err = spec
+            .profiles(
+                std::path::Path::new("."),
+                Vec::new(),
+                vec![group.into()],
+                &crate::profile::Specifier::empty(),
+            )
+            .unwrap_err();
+        config.locate(err).to_string()
+    }
+
+    #[test]
+    fn error_in_matrix() {
+        assert_eq!(
+            [
+                "'all-lua5.1' is defined as both a profile and a group",
+                " --> lunest.toml:3:8",
+                "  |",
+                "3 | lua = [\"lua5.1\"]",
+                "  |        ^^^^^^^^",
+            ]
+            .join("\n"),
+            located_error(
+                "group.\"all-lua5.1\" = []\n[matrix.all]\nlua = [\"lua5.1\"]\n",
+                "all"
+            ),
+        );
+    }
+
+    #[test]
+    fn duplicate_matrix_entry() {
+        assert_eq!(
+            [
+                "duplicate entry 'lua5.1' in 'lua' axis of 'all' matrix",
+                " --> lunest.toml:2:18",
+                "  |",
+                "2 | lua = [\"lua5.1\", \"/opt/lua5.1\"]",
+                "  |                  ^^^^^^^^^^^^^",
+                "  = help: profile names are generated from the file names of the programs",
+            ]
+            .join("\n"),
+            located_error("[matrix.all]\nlua = [\"lua5.1\", \"/opt/lua5.1\"]\n", "all"),
+        );
+        assert!(
+            located_error("[matrix.all]\nenv.A = [\"a\", \"a\"]\n", "all")
+                .starts_with("duplicate entry 'a' in 'env.A' axis of 'all' matrix\n")
+        );
+    }
+
+    #[test]
+    fn negative_limit() {
+        let config = crate::config::tests::file("[profile.a]\ninstruction_limit = -1\n");
+        assert_eq!(
+            [
+                "invalid value: integer `-1`, expected u64",
+                " --> lunest.toml:2:21",
+                "  |",
+                "2 | instruction_limit = -1",
+                "  |                     ^^",
+            ]
+            .join("\n"),
+            config.parse::<Specifier>().unwrap_err().to_string(),
+        );
+    }
+
+    #[test]
+    fn overrides() -> anyhow::Result<()> {
+        let root_dir = tempfile::tempdir()?;
+        let overrides = crate::profile::Specifier {
+            lua: Some(vec!["nvim".into(), "-l".into()]),
+            ..crate::profile::Specifier::empty()
+        };
+        let spec: Specifier = toml::from_str(
+            r#"
+            group.all = ["a", "b"]
+            profile.a = { lua = ["lua5.1"], env = { A = "a" } }
+            profile.b = {}
+            "#,
+        )?;
+        let profiles =
+            spec.profiles(root_dir.path(), Vec::new(), vec!["all".into()], &overrides)?;
+        for p in &profiles {
+            assert_eq!(std::ffi::OsStr::new("nvim"), p.lua_command().get_program());
+        }
+        let profiles =
+            Specifier::ad_hoc().profiles(root_dir.path(), Vec::new(), Vec::new(), &overrides)?;
+        assert_eq!("default", profiles[0].name());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod target_tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file = temp_dir.path().join("a.lua");
+        std::fs::write(&file, "")?;
+        let file = std::fs::canonicalize(file)?;
+        let parse = |s: &std::path::Path| Target::parse(&s.display().to_string());
+        assert_eq!(
+            Target {
+                path: file.clone(),
+                line: None,
+            },
+            parse(&file)?,
+        );
+        assert_eq!(
+            Target {
+                path: file.clone(),
+                line: Some(42),
+            },
+            parse(&file.with_file_name("a.lua:42"))?,
+        );
+        assert!(parse(&file.with_file_name("b.lua")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn contains_file() {
+        let target = Target {
+            path: "/a/b".into(),
+            line: None,
+        };
+        assert!(target.contains_file(std::path::Path::new("/a/b")));
+        assert!(target.contains_file(std::path::Path::new("/a/b/c.lua")));
+        assert!(!target.contains_file(std::path::Path::new("/a/bc.lua")));
+    }
+}
+
+#[cfg(test)]
+mod is_test_selected_tests {
+    use super::*;
+    use crate::process::{LineRange, TestInfo};
+
+    fn test(id: &str, source: &str, line_ranges: &[(u32, u32)]) -> TestInfo {
+        TestInfo {
+            id: id.into(),
+            title: vec![id.into()],
+            source: source.into(),
+            line_ranges: line_ranges
+                .iter()
+                .map(|&(first, last)| LineRange { first, last })
+                .collect(),
+            has_line_range: true,
+        }
+    }
+
+    /// group (1-20) { a (2-4), group (6-15) { b (7-9), c (11-13) }, d (17-19) } in /a/foo.lua
+    fn tests() -> Vec<TestInfo> {
+        vec![
+            test("a", "/a/foo.lua", &[(1, 20), (2, 4)]),
+            test("b", "/a/foo.lua", &[(1, 20), (6, 15), (7, 9)]),
+            test("c", "/a/foo.lua", &[(1, 20), (6, 15), (11, 13)]),
+            test("d", "/a/foo.lua", &[(1, 20), (17, 19)]),
+            test("e", "/a/bar.lua", &[(1, 20)]),
+        ]
+    }
+
+    #[rstest::rstest]
+    #[case(&["a", "b", "c", "d", "e"], None)]
+    #[case(&["a", "b", "c", "d"], Some(("/a/foo.lua", None)))]
+    #[case(&["a"], Some(("/a/foo.lua", Some(3))))]
+    #[case(&["a", "b", "c", "d"], Some(("/a/foo.lua", Some(5))))]
+    #[case(&["b"], Some(("/a/foo.lua", Some(8))))]
+    #[case(&["b", "c"], Some(("/a/foo.lua", Some(10))))]
+    #[case(&["b", "c"], Some(("/a/foo.lua", Some(6))))]
+    #[case(&[], Some(("/a/foo.lua", Some(30))))]
+    #[case(&["e"], Some(("/a/bar.lua", Some(3))))]
+    fn is_test_selected(#[case] expected: &[&str], #[case] target: Option<(&str, Option<u32>)>) {
+        let targets = target
+            .map(|(path, line)| Target {
+                path: path.into(),
+                line,
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        let tests = tests();
+        let selected = tests
+            .iter()
+            .filter(|info| super::is_test_selected(&targets, info, &tests))
+            .map(|info| info.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, selected);
+    }
+}
+
+#[cfg(test)]
+mod split_command_tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case(vec!["lua"], "lua")]
+    #[case(vec!["nvim", "-l"], "  nvim   -l ")]
+    #[case(vec!["/a b/lua", "-e", "print('x')"], r#""/a b/lua" -e "print('x')""#)]
+    #[case(vec!["a", ""], "a ''")]
+    fn ok(#[case] expected: Vec<&str>, #[case] command: &str) {
+        assert_eq!(expected, split_command(command).unwrap());
+    }
+
+    #[test]
+    fn unclosed_quote() {
+        assert!(split_command("lua 'a").is_err());
+    }
+}
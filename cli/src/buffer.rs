@@ -2,7 +2,8 @@ use tokio::io::AsyncBufReadExt;
 
 pub struct AsyncLineReader<R> {
     reader: tokio::io::BufReader<R>,
-    buffer: String,
+    /// Bytes of an incomplete line, kept if a read is cancelled
+    buffer: Vec<u8>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -16,22 +17,25 @@ impl<R: tokio::io::AsyncRead> AsyncLineReader<R> {
     pub fn new(reader: R) -> Self {
         Self {
             reader: tokio::io::BufReader::new(reader),
-            buffer: String::new(),
+            buffer: Vec::new(),
         }
     }
 }
 
 impl<R: tokio::io::AsyncRead + std::marker::Unpin> AsyncLineReader<R> {
+    /// Cancel safe, unlike [`AsyncBufReadExt::read_line`].
     pub async fn read_line(&mut self) -> std::io::Result<Line> {
-        Ok(if self.reader.read_line(&mut self.buffer).await? == 0 {
-            Line::Empty
-        } else if !self.buffer.ends_with('\n') {
-            Line::NoLF
-        } else {
-            let s = self.buffer.to_string();
-            self.buffer.clear();
-            Line::Ok(s)
-        })
+        Ok(
+            if self.reader.read_until(b'\n', &mut self.buffer).await? == 0 {
+                Line::Empty
+            } else if !self.buffer.ends_with(b"\n") {
+                Line::NoLF
+            } else {
+                let s = String::from_utf8_lossy(&self.buffer).into_owned();
+                self.buffer.clear();
+                Line::Ok(s)
+            },
+        )
     }
 }
 
@@ -0,0 +1,57 @@
+/// Returns the directory where lunest keeps files across runs.
+pub fn dir() -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os("LUNEST_CACHE_DIR").filter(|s| !s.is_empty()) {
+        return Some(dir.into());
+    }
+    let env = |key| {
+        std::env::var_os(key)
+            .filter(|s| !s.is_empty())
+            .map(std::path::PathBuf::from)
+    };
+    let base = if cfg!(windows) {
+        env("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| home.join("Library").join("Caches"))
+    } else {
+        env("XDG_CACHE_HOME").or_else(|| env("HOME").map(|home| home.join(".cache")))
+    };
+    base.map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Returns the directory for the embedded interpreters.
+pub fn lua_dir() -> Option<std::path::PathBuf> {
+    dir().map(|dir| dir.join("lua"))
+}
+
+/// Removes the cache directory and returns `false` if it doesn't exist.
+pub fn clean() -> std::io::Result<bool> {
+    let Some(dir) = dir() else {
+        return Ok(false);
+    };
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Extracts the embedded interpreter into the cache directory, or into `temp_dir` if caching is
+/// disabled or fails.
+pub fn extract_lua(
+    lua: lua_rt::Lua,
+    use_cache: bool,
+    temp_dir: &std::path::Path,
+) -> std::io::Result<std::path::PathBuf> {
+    if use_cache && let Some(dir) = lua_dir() {
+        match lua.install(&dir) {
+            Ok(path) => {
+                log::debug!("using cached interpreter: {path:?}");
+                return Ok(path);
+            }
+            Err(e) => log::warn!("failed to cache the interpreter: {e}"),
+        }
+    }
+    let path = temp_dir.join(lua.recommended_program_name());
+    lua.write(&path)?;
+    Ok(path)
+}
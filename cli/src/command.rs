@@ -3,6 +3,7 @@ pub struct Builder {
     program: std::ffi::OsString,
     args: Vec<std::ffi::OsString>,
     env: std::collections::HashMap<std::ffi::OsString, std::ffi::OsString>,
+    env_clear: bool,
     cwd: Option<std::path::PathBuf>,
 }
 
@@ -12,6 +13,7 @@ impl Builder {
             program: program.into(),
             args: Vec::new(),
             env: std::collections::HashMap::new(),
+            env_clear: false,
             cwd: None,
         }
     }
@@ -25,6 +27,17 @@ impl Builder {
         &self.program
     }
 
+    #[cfg(feature = "in-process")]
+    pub fn get_args(&self) -> &[std::ffi::OsString] {
+        &self.args
+    }
+
+    /// Returns `true` if the environment differs from the current process.
+    #[cfg(feature = "in-process")]
+    pub fn has_env(&self) -> bool {
+        self.env_clear || !self.env.is_empty()
+    }
+
     pub fn arg(&mut self, arg: impl Into<std::ffi::OsString>) -> &mut Self {
         self.args.push(arg.into());
         self
@@ -47,6 +60,12 @@ impl Builder {
         self
     }
 
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
     pub fn current_dir(&mut self, dir: impl Into<std::path::PathBuf>) -> &mut Self {
         self.cwd.replace(dir.into());
         self
@@ -55,6 +74,9 @@ impl Builder {
     pub fn build(&self) -> std::process::Command {
         let mut cmd = std::process::Command::new(&self.program);
         cmd.args(self.args.iter());
+        if self.env_clear {
+            cmd.env_clear();
+        }
         cmd.envs(self.env.iter());
         if let Some(dir) = &self.cwd {
             cmd.current_dir(dir);
@@ -97,6 +119,9 @@ impl<'a> Display<'a> {
 impl std::fmt::Display for Display<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.env {
+            if self.builder.env_clear {
+                write!(f, "env -i ")?;
+            }
             for (key, val) in &self.builder.env {
                 write!(f, "{}='{}' ", key.to_string_lossy(), val.to_string_lossy())?;
             }
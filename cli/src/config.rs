@@ -0,0 +1,195 @@
+/// Contents of a configuration file.
+#[derive(Debug)]
+pub struct File {
+    path: std::path::PathBuf,
+    contents: String,
+}
+
+/// An error pointing at a byte range of a configuration file.
+#[derive(thiserror::Error, Debug)]
+#[error("{message}")]
+pub struct SpannedError {
+    span: std::ops::Range<usize>,
+    message: String,
+    help: Option<String>,
+}
+
+/// A [`SpannedError`] rendered with the location and the snippet of the configuration file.
+#[derive(thiserror::Error, Debug)]
+pub struct Error {
+    path: std::path::PathBuf,
+    line: usize,
+    column: usize,
+    snippet: String,
+    width: usize,
+    message: String,
+    help: Option<String>,
+}
+
+impl File {
+    pub fn read(path: std::path::PathBuf) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(Self { path, contents })
+    }
+
+    pub fn parse<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        toml::from_str(&self.contents).map_err(|e| {
+            let message = e.message().trim_end().to_string();
+            let help = suggest_field(&message).map(|s| format!("did you mean `{s}`?"));
+            self.error(SpannedError {
+                span: e.span().unwrap_or(0..0),
+                message,
+                help,
+            })
+        })
+    }
+
+    pub fn error(&self, err: SpannedError) -> Error {
+        let start = err.span.start.min(self.contents.len());
+        let end = err.span.end.clamp(start, self.contents.len());
+        let line_start = self.contents[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.contents[start..]
+            .find('\n')
+            .map_or(self.contents.len(), |i| start + i);
+        let snippet = self.contents[line_start..line_end].trim_end_matches('\r');
+        Error {
+            path: self.path.clone(),
+            line: self.contents[..line_start].matches('\n').count() + 1,
+            column: self.contents[line_start..start].chars().count() + 1,
+            snippet: snippet.to_string(),
+            width: self.contents[start..end.min(line_end)]
+                .chars()
+                .count()
+                .max(1),
+            message: err.message,
+            help: err.help,
+        }
+    }
+
+    /// Attaches the location to `err` if it is a [`SpannedError`].
+    pub fn locate(&self, err: anyhow::Error) -> anyhow::Error {
+        match err.downcast::<SpannedError>() {
+            Ok(e) => self.error(e).into(),
+            Err(e) => e,
+        }
+    }
+}
+
+impl SpannedError {
+    pub fn new(span: std::ops::Range<usize>, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{}", self.message)?;
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.path.display(),
+            self.line,
+            self.column,
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width),
+        )?;
+        if let Some(help) = &self.help {
+            write!(f, "\n{gutter} = help: {help}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the candidate that looks like a typo of `name`.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (strsim::jaro(name, c), c))
+        .filter(|(confidence, _)| 0.7 < *confidence)
+        .max_by(|a, b| a.0.total_cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+        .map(|(_, c)| c)
+}
+
+/// Finds the suggestion for serde's "unknown field `foo`, expected ..." message.
+fn suggest_field(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let (field, expected) = rest.split_once('`')?;
+    let candidates = expected.split('`').skip(1).step_by(2);
+    suggest(field, candidates)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Spec {
+        include: Option<Vec<String>>,
+        exclude: Option<Vec<String>>,
+    }
+
+    pub fn file(contents: &str) -> File {
+        File {
+            path: "lunest.toml".into(),
+            contents: contents.into(),
+        }
+    }
+
+    #[test]
+    fn unknown_field() {
+        let err = file("include = []\nexclud = []\n")
+            .parse::<Spec>()
+            .unwrap_err();
+        assert_eq!((2, 1, 6), (err.line, err.column, err.width));
+        assert_eq!(Some("did you mean `exclude`?"), err.help.as_deref());
+        assert_eq!(
+            [
+                "unknown field `exclud`, expected `include` or `exclude`",
+                " --> lunest.toml:2:1",
+                "  |",
+                "2 | exclud = []",
+                "  | ^^^^^^",
+                "  = help: did you mean `exclude`?",
+            ]
+            .join("\n"),
+            err.to_string(),
+        );
+    }
+
+    #[test]
+    fn spanned_error() {
+        let f = file("a = 1\nb = [\"x\", \"yz\"]\r\n");
+        let err = f.error(SpannedError::new(16..20, "invalid"));
+        assert_eq!((2, 11, 4), (err.line, err.column, err.width));
+        assert_eq!("b = [\"x\", \"yz\"]", err.snippet);
+    }
+
+    #[rstest::rstest]
+    #[case(Some("exclude"), "exclud")]
+    #[case(Some("lua54"), "lua45")]
+    #[case(None, "foo")]
+    fn suggestion(#[case] expected: Option<&str>, #[case] name: &str) {
+        assert_eq!(
+            expected,
+            suggest(name, ["lua", "include", "exclude", "init", "lua54"])
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::mpsc;
+
+/// An embedded interpreter running on its own thread.
+///
+/// `print` and `io.write` of the tests are sent as logs by the main script, but writing to
+/// `io.stdout` or `io.stderr` directly still goes to the terminal. `os.exit` raises an error.
+pub struct Runtime {
+    input: mpsc::Sender<Vec<u8>>,
+    output: tokio::sync::mpsc::UnboundedReceiver<String>,
+    thread: Option<std::thread::JoinHandle<std::io::Result<i32>>>,
+}
+
+struct Host {
+    input: mpsc::Receiver<Vec<u8>>,
+    output: tokio::sync::mpsc::UnboundedSender<String>,
+    idle: bool,
+}
+
+impl Runtime {
+    pub fn spawn(
+        lua: lua_rt::Lua,
+        script: std::path::PathBuf,
+        limits: lua_rt::Limits,
+    ) -> std::io::Result<Self> {
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut host = Host {
+            input: input_rx,
+            output: output_tx,
+            idle: true,
+        };
+        let thread = std::thread::Builder::new()
+            .name(format!("lunest-{}", lua.recommended_program_name()))
+            .spawn(move || lua.run_in_process(&script, &limits, &mut host))?;
+        Ok(Self {
+            input: input_tx,
+            output: output_rx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the next line, or `None` if the script has finished.
+    pub async fn read_line(&mut self) -> Option<String> {
+        self.output.recv().await
+    }
+
+    pub fn write_line(&self, line: Vec<u8>) -> std::io::Result<()> {
+        self.input
+            .send(line)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+
+    /// Returns the exit status if the script has finished, or the error if it couldn't run.
+    pub fn try_wait(&mut self) -> std::io::Result<Option<i32>> {
+        if !self.thread.as_ref().is_some_and(|t| t.is_finished()) {
+            return Ok(None);
+        }
+        let thread = self.thread.take().unwrap();
+        thread.join().unwrap_or(Ok(101)).map(Some)
+    }
+}
+
+impl lua_rt::Host for Host {
+    fn read(&mut self) -> Option<Vec<u8>> {
+        // Returns `None` once after each batch of inputs, so that the script reports
+        // `AllInputsRead`, and blocks after that instead of polling.
+        let line = match self.input.try_recv() {
+            Ok(line) => line,
+            Err(mpsc::TryRecvError::Empty) if !self.idle => {
+                self.idle = true;
+                return None;
+            }
+            Err(mpsc::TryRecvError::Empty) => match self.input.recv() {
+                Ok(line) => line,
+                Err(mpsc::RecvError) => FINISH.to_vec(),
+            },
+            // the host has gone without finishing the script
+            Err(mpsc::TryRecvError::Disconnected) => FINISH.to_vec(),
+        };
+        self.idle = false;
+        Some(line)
+    }
+
+    fn write(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        _ = self.output.send(line.trim_end_matches('\n').to_string());
+    }
+}
+
+const FINISH: &[u8] = b"{\"t\":\"Finish\"}\n";
+
+#[cfg(test)]
+mod runtime_tests {
+    use super::*;
+    use crate::process::{Input, Output, TargetFile, TestMode};
+
+    async fn wait(runtime: &mut Runtime) -> std::io::Result<i32> {
+        loop {
+            if let Some(code) = runtime.try_wait()? {
+                return Ok(code);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn run() -> anyhow::Result<()> {
+        let t = tempfile::tempdir()?;
+        let main_script = t.path().join("main.lua");
+        std::fs::write(&main_script, crate::app::MAIN_SCRIPT)?;
+        let test_file = t.path().join("a.lua");
+        std::fs::write(
+            &test_file,
+            r#"
+                local test = require("lunest")
+                test.test("ok", function() print("x", 1) end)
+                test.test("err", function() error("x") end)
+            "#,
+        )?;
+
+        let mut runtime = Runtime::spawn(
+            lua_rt::Lua::default(),
+            main_script,
+            lua_rt::Limits::default(),
+        )?;
+        let inputs = [
+            Input::Initialize {
+                target_files: vec![TargetFile::from_path(test_file, t.path())],
+                root_dir: t.path().to_path_buf(),
+                cwd: t.path().to_path_buf(),
+                path: Vec::new(),
+                cpath: Vec::new(),
+                term_width: 60,
+            },
+            Input::Run {
+                test_id_filter: None,
+                test_mode: TestMode::Run,
+            },
+            Input::Finish,
+        ];
+        for input in inputs {
+            let mut json = serde_json::to_vec(&input)?;
+            json.push(b'\n');
+            runtime.write_line(json)?;
+        }
+
+        let mut results = Vec::new();
+        let mut logs = Vec::new();
+        while let Some(line) = runtime.read_line().await {
+            match serde_json::from_str(&line)? {
+                Output::TestFinished(result) => {
+                    results.push((result.title().join(" :: "), result.success()));
+                }
+                Output::Log(s) => logs.push(s),
+                _ => (),
+            }
+        }
+        assert_eq!(
+            vec![
+                ("a.lua :: ok".to_string(), true),
+                ("a.lua :: err".into(), false)
+            ],
+            results,
+        );
+        assert!(logs.contains(&"x\t1".to_string()));
+        assert_eq!(0, wait(&mut runtime).await?);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "checked", feature = "lua54"))]
+    #[tokio::test]
+    async fn unsupported() -> anyhow::Result<()> {
+        let mut runtime = Runtime::spawn(
+            lua_rt::Lua::Lua54Checked,
+            "main.lua".into(),
+            lua_rt::Limits::default(),
+        )?;
+        assert_eq!(None, runtime.read_line().await);
+        let err = wait(&mut runtime).await.unwrap_err();
+        assert_eq!(std::io::ErrorKind::Unsupported, err.kind());
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::Write as _;
+
+use crate::process::{LineRange, TestInfo};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// One title per line
+    #[default]
+    Flat,
+    /// Titles indented by groups
+    Tree,
+    /// JSON array of the tests of all profiles
+    Json,
+    /// One test ID per line
+    Ids,
+}
+
+impl Format {
+    /// Returns `true` if the output is meant to be read by humans.
+    pub fn is_human_readable(&self) -> bool {
+        matches!(self, Format::Flat | Format::Tree)
+    }
+}
+
+pub fn flat(tests: &[TestInfo]) -> String {
+    let mut s = String::new();
+    for info in tests {
+        _ = writeln!(s, "{info}");
+    }
+    s
+}
+
+pub fn tree(tests: &[TestInfo]) -> String {
+    let mut s = String::new();
+    let mut prev: Vec<&str> = Vec::new();
+    for info in tests {
+        let ancestors = ancestor_ids(&info.id);
+        let common = prev
+            .iter()
+            .zip(&ancestors)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, name) in info.title.iter().enumerate().skip(common) {
+            _ = writeln!(s, "{}{name}", "  ".repeat(depth));
+        }
+        prev = ancestors;
+    }
+    s
+}
+
+pub fn ids(tests: &[TestInfo]) -> String {
+    let mut s = String::new();
+    for info in tests {
+        _ = writeln!(s, "{}", info.id);
+    }
+    s
+}
+
+pub fn counts(tests: &[TestInfo]) -> String {
+    let mut s = String::new();
+    for (file, count) in count_per_file(tests) {
+        _ = writeln!(s, "{file}: {count}");
+    }
+    _ = writeln!(s, "total: {}", tests.len());
+    s
+}
+
+/// Returns a `PROFILE<TAB>COUNT` line, for the formats without the headers of the profiles.
+pub fn profile_count(profile: &str, tests: &[TestInfo]) -> String {
+    format!("{profile}\t{}\n", tests.len())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct JsonTest<'a> {
+    profile: &'a str,
+    id: &'a str,
+    title: &'a [String],
+    source: &'a std::path::Path,
+    line_range: Option<&'a LineRange>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct JsonCount<'a> {
+    profile: &'a str,
+    files: indexmap::IndexMap<&'a str, usize>,
+    total: usize,
+}
+
+pub fn json_tests<'a>(profile: &'a str, tests: &'a [TestInfo]) -> Vec<JsonTest<'a>> {
+    tests
+        .iter()
+        .map(|info| JsonTest {
+            profile,
+            id: &info.id,
+            title: &info.title,
+            source: &info.source,
+            line_range: info.line_range(),
+        })
+        .collect()
+}
+
+pub fn json_count<'a>(profile: &'a str, tests: &'a [TestInfo]) -> JsonCount<'a> {
+    JsonCount {
+        profile,
+        files: count_per_file(tests),
+        total: tests.len(),
+    }
+}
+
+fn count_per_file(tests: &[TestInfo]) -> indexmap::IndexMap<&str, usize> {
+    let mut files = indexmap::IndexMap::new();
+    for info in tests {
+        let file = info.title.first().map_or("", String::as_str);
+        *files.entry(file).or_default() += 1;
+    }
+    files
+}
+
+/// Splits the ID in the same way as `create_set` in `lunest/id.lua`.
+fn ancestor_ids(id: &str) -> Vec<&str> {
+    let Some(i) = id.find(":/") else {
+        return vec![id];
+    };
+    let mut r = vec![&id[..=i]];
+    r.extend(
+        id[i + 2..]
+            .match_indices('/')
+            .map(|(j, _)| &id[..i + 2 + j]),
+    );
+    r.push(id);
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: &str, title: &[&str]) -> TestInfo {
+        TestInfo {
+            id: id.into(),
+            title: title.iter().map(|s| s.to_string()).collect(),
+            source: "a.lua".into(),
+            line_ranges: Vec::new(),
+            has_line_range: false,
+        }
+    }
+
+    #[test]
+    fn ancestors() {
+        assert_eq!(
+            vec!["a/b.lua:", "a/b.lua:/1", "a/b.lua:/1/a", "a/b.lua:/1/a/2"],
+            ancestor_ids("a/b.lua:/1/a/2"),
+        );
+    }
+
+    #[test]
+    fn tree_and_counts() {
+        let tests = [
+            info("a.lua:/1", &["a.lua", "x"]),
+            info("a.lua:/2/1", &["a.lua", "g", "y"]),
+            info("a.lua:/2/2", &["a.lua", "g", "z"]),
+            info("a.lua:/3/1", &["a.lua", "g", "y"]),
+            info("b.lua:/1", &["b.lua", "x"]),
+        ];
+        assert_eq!(
+            [
+                "a.lua", "  x", "  g", "    y", "    z", "  g", "    y", "b.lua", "  x", "",
+            ]
+            .join("\n"),
+            tree(&tests),
+        );
+        assert_eq!("a.lua: 4\nb.lua: 1\ntotal: 5\n", counts(&tests));
+        assert_eq!("default\t5\n", profile_count("default", &tests));
+    }
+}
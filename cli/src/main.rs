@@ -1,11 +1,18 @@
 mod app;
 mod buffer;
+mod cache;
 mod command;
+mod config;
+#[cfg(feature = "in-process")]
+mod in_process;
+mod list;
 mod process;
 mod profile;
+mod transport;
 
 use std::io::Write;
 
+use anyhow::Context as _;
 use clap::Parser;
 use crossterm::style::Stylize;
 
@@ -17,6 +24,9 @@ async fn main() -> anyhow::Result<std::process::ExitCode> {
         Args::Run(c) => c.exec().await?,
         Args::List(c) => c.exec().await?,
         Args::Wrapper(c) => c.exec()?,
+        Args::Lua(c) => c.exec()?,
+        Args::Cache(c) => c.exec()?,
+        Args::Bundle(c) => c.exec()?,
     };
     Ok(code)
 }
@@ -39,6 +49,16 @@ enum Args {
 
     /// Print wrapper Lua code used for in-source testing
     Wrapper(WrapperCommand),
+
+    /// Run the embedded Lua interpreter, or start a REPL if no script is given
+    Lua(LuaCommand),
+
+    /// Manage the cache directory
+    #[command(subcommand)]
+    Cache(CacheCommand),
+
+    /// Bundle a module and the modules it requires into a single file
+    Bundle(BundleCommand),
 }
 
 #[derive(clap::Args, Debug)]
@@ -78,6 +98,9 @@ async fn run(app: &app::App, profile: &profile::Profile) -> anyhow::Result<bool>
     process
         .write(&process::Input::Initialize {
             root_dir: app.root_dir().to_path_buf(),
+            cwd: profile.cwd().to_path_buf(),
+            path: profile.package_path().to_vec(),
+            cpath: profile.package_cpath().to_vec(),
             target_files: profile
                 .target_files()
                 .iter()
@@ -93,7 +116,7 @@ async fn run(app: &app::App, profile: &profile::Profile) -> anyhow::Result<bool>
             .await?;
     }
 
-    let ids = get_test_list(&mut process)
+    let ids = get_test_list(app, &mut process)
         .await?
         .into_iter()
         .map(|info| info.id)
@@ -110,16 +133,32 @@ async fn run(app: &app::App, profile: &profile::Profile) -> anyhow::Result<bool>
     let mut results = Vec::new();
     println!();
 
+    // malformed lines reported as the errors of the tests that wrote them
+    let mut malformed = Vec::new();
     loop {
-        let Some(output) = process.read().await? else {
-            if process.is_running()? {
+        let output = match process.read().await {
+            Err(e @ process::Error::Protocol { .. }) => {
+                keep_protocol_error(e, &mut malformed);
+                continue;
+            }
+            output => output?,
+        };
+        let Some(output) = output else {
+            if process.is_running().await? {
                 continue;
             } else {
                 break;
             }
         };
         match output {
-            process::Output::TestFinished(t) => {
+            process::Output::TestFinished(mut t) => {
+                malformed.retain(|(title, e)| {
+                    let matched = title.as_slice() == t.title();
+                    if matched {
+                        t.fail_with(e);
+                    }
+                    !matched
+                });
                 println!("{t}");
                 results.push(t);
             }
@@ -131,6 +170,15 @@ async fn run(app: &app::App, profile: &profile::Profile) -> anyhow::Result<bool>
         }
     }
 
+    // the tests didn't finish properly
+    for (title, e) in malformed {
+        if results.iter().all(|r| r.title() != title.as_slice()) {
+            let t = process::TestFinished::from_error(title, &e);
+            println!("{t}");
+            results.push(t);
+        }
+    }
+
     let (success, error): (Vec<_>, Vec<_>) = results.iter().partition(|r| r.success());
     println!(
         "\nsuccess: {}, error: {}",
@@ -140,10 +188,28 @@ async fn run(app: &app::App, profile: &profile::Profile) -> anyhow::Result<bool>
     Ok(error.is_empty())
 }
 
+/// Keeps a malformed output to report it as the error of the test that wrote it, or prints it if
+/// no test was running.
+fn keep_protocol_error(e: process::Error, malformed: &mut Vec<(Vec<String>, process::Error)>) {
+    match &e {
+        process::Error::Protocol {
+            test: Some(title), ..
+        } => malformed.push((title.clone(), e)),
+        _ => eprintln!("{}: {e}", "warning".yellow().bold()),
+    }
+}
+
 #[derive(clap::Args, Debug)]
 struct ListCommand {
     #[clap(flatten)]
     app_context_options: app::Options,
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    format: list::Format,
+    /// Print the number of tests per file and per profile instead of the tests, or
+    /// `PROFILE<TAB>COUNT` lines with `--format ids`
+    #[arg(long)]
+    count: bool,
 }
 
 impl ListCommand {
@@ -152,24 +218,56 @@ impl ListCommand {
 
         let app = app::App::new(self.app_context_options)?;
 
+        let mut json = Vec::new();
         for (i, profile) in app.profiles().iter().enumerate() {
-            if i != 0 {
+            if self.format.is_human_readable() {
+                if i != 0 {
+                    println!();
+                }
+                println!("run with profile '{}'", profile.name().bold());
                 println!();
             }
-            list(&app, profile).await?;
+            let tests = list(&app, profile).await?;
+            match (self.format, self.count) {
+                (list::Format::Json, false) => {
+                    for t in list::json_tests(profile.name(), &tests) {
+                        json.push(serde_json::to_value(t)?);
+                    }
+                }
+                (list::Format::Json, true) => {
+                    json.push(serde_json::to_value(list::json_count(
+                        profile.name(),
+                        &tests,
+                    ))?);
+                }
+                (list::Format::Ids, true) => {
+                    print!("{}", list::profile_count(profile.name(), &tests));
+                }
+                (_, true) => print!("{}", list::counts(&tests)),
+                (list::Format::Flat, false) => print!("{}", list::flat(&tests)),
+                (list::Format::Tree, false) => print!("{}", list::tree(&tests)),
+                (list::Format::Ids, false) => print!("{}", list::ids(&tests)),
+            }
+        }
+        if self.format == list::Format::Json {
+            println!("{}", serde_json::to_string_pretty(&json)?);
         }
         Ok(std::process::ExitCode::SUCCESS)
     }
 }
 
-async fn list(app: &app::App, profile: &profile::Profile) -> anyhow::Result<()> {
-    println!("run with profile '{}'", profile.name().bold());
-
+async fn list(
+    app: &app::App,
+    profile: &profile::Profile,
+) -> anyhow::Result<Vec<process::TestInfo>> {
     let mut process = process::Process::spawn(app, profile).await?;
 
     process
         .write(&process::Input::Initialize {
             root_dir: app.root_dir().to_path_buf(),
+            cwd: profile.cwd().to_path_buf(),
+            path: profile.package_path().to_vec(),
+            cpath: profile.package_cpath().to_vec(),
             target_files: profile
                 .target_files()
                 .iter()
@@ -184,27 +282,34 @@ async fn list(app: &app::App, profile: &profile::Profile) -> anyhow::Result<()>
             .await?;
     }
 
-    println!();
-    let test_list = get_test_list(&mut process).await?;
-    for info in &test_list {
-        println!("{info}");
-    }
-
-    Ok(())
+    get_test_list(app, &mut process).await
 }
 
-async fn get_test_list(process: &mut process::Process) -> anyhow::Result<Vec<process::TestInfo>> {
+async fn get_test_list(
+    app: &app::App,
+    process: &mut process::Process,
+) -> anyhow::Result<Vec<process::TestInfo>> {
     process
         .write(&process::Input::Run {
             test_id_filter: None,
             test_mode: process::TestMode::SendInfo,
         })
         .await?;
+    if process.supports(process::Feature::Flush) {
+        process.flush_inputs().await?;
+    }
 
     let mut list = Vec::new();
     loop {
-        let Some(output) = process.read().await? else {
-            anyhow::ensure!(process.is_running()?);
+        let output = match process.read().await {
+            Err(e @ process::Error::Protocol { .. }) => {
+                eprintln!("{}: {e}", "warning".yellow().bold());
+                continue;
+            }
+            output => output?,
+        };
+        let Some(output) = output else {
+            anyhow::ensure!(process.is_running().await?);
             continue;
         };
         match output {
@@ -218,6 +323,13 @@ async fn get_test_list(process: &mut process::Process) -> anyhow::Result<Vec<pro
         }
     }
 
+    // selected after all are read, since a line may be inside any of them
+    let selected = list
+        .iter()
+        .map(|info| app.is_test_selected(info, &list))
+        .collect::<Vec<_>>();
+    let mut selected = selected.into_iter();
+    list.retain(|_| selected.next().unwrap());
     Ok(list)
 }
 
@@ -244,3 +356,133 @@ impl WrapperCommand {
         Ok(std::process::ExitCode::SUCCESS)
     }
 }
+
+#[derive(clap::Args, Debug)]
+#[command(disable_version_flag = true)]
+struct LuaCommand {
+    /// Lua version to run (5.1, 5.2, 5.3, 5.4 or jit) [default: the latest embedded one]
+    #[arg(long, short = 'v', value_name = "VERSION", value_parser = parse_lua_version)]
+    version: Option<lua_rt::Lua>,
+    /// Don't reuse the embedded Lua interpreters extracted by previous runs
+    #[arg(long)]
+    no_cache: bool,
+    /// Script and arguments passed to it
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "SCRIPT [ARGS]"
+    )]
+    args: Vec<std::ffi::OsString>,
+}
+
+impl LuaCommand {
+    fn exec(&self) -> anyhow::Result<std::process::ExitCode> {
+        let temp_dir = tempfile::tempdir()?;
+        let program = cache::extract_lua(
+            self.version.unwrap_or_default(),
+            !self.no_cache,
+            temp_dir.path(),
+        )?;
+        log::debug!("running {program:?} with {:?}", self.args);
+        let status = std::process::Command::new(&program)
+            .args(&self.args)
+            .status()
+            .with_context(|| format!("failed to run {}", program.display()))?;
+        // codes out of the range of `u8` (e.g. on Windows) would be truncated to 0 by a cast
+        Ok(match status.code().map(u8::try_from) {
+            Some(Ok(code)) => std::process::ExitCode::from(code),
+            Some(Err(_)) | None => std::process::ExitCode::FAILURE,
+        })
+    }
+}
+
+fn parse_lua_version(s: &str) -> Result<lua_rt::Lua, String> {
+    let name = if s.starts_with("lua") {
+        s.to_string()
+    } else {
+        format!("lua{s}")
+    };
+    lua_rt::Lua::from_program_name(&name).ok_or_else(
+        || match lua_rt::Lua::features_for_program_name(&name) {
+            Some(features) => format!("lunest needs {}", app::describe_features(features)),
+            None => format!("unknown Lua version '{s}'"),
+        },
+    )
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CacheCommand {
+    /// Remove the cached files
+    Clean,
+    /// Print the path to the cache directory
+    Dir,
+}
+
+impl CacheCommand {
+    fn exec(&self) -> anyhow::Result<std::process::ExitCode> {
+        match self {
+            CacheCommand::Clean => {
+                if cache::clean()? {
+                    println!("cache directory removed");
+                } else {
+                    println!("cache directory not found");
+                }
+            }
+            CacheCommand::Dir => {
+                let dir = cache::dir().context("cache directory is not available")?;
+                println!("{}", dir.display());
+            }
+        }
+        Ok(std::process::ExitCode::SUCCESS)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct BundleCommand {
+    /// Module to start from, which the bundle returns
+    #[arg(value_name = "MODULE")]
+    entry: String,
+    /// Directory to search modules in as `?.lua` and `?/init.lua` [default: .]
+    #[arg(long = "path", short = 'I', value_name = "DIR")]
+    paths: Vec<std::path::PathBuf>,
+    /// Module to register to the global `package.loaded` instead of the bundle's private one
+    #[arg(long = "public", value_name = "MODULE")]
+    publics: Vec<String>,
+    /// Write the bundle into the specified file
+    #[arg(long, short, value_name = "FILE")]
+    out: Option<std::path::PathBuf>,
+    /// Remove in-source tests defined with `test` and `group` of lunest
+    #[arg(long)]
+    strip_tests: bool,
+}
+
+impl BundleCommand {
+    fn exec(&self) -> anyhow::Result<std::process::ExitCode> {
+        let paths = if self.paths.is_empty() {
+            vec![std::path::PathBuf::from(".")]
+        } else {
+            self.paths.clone()
+        };
+        let mut bundler = bundler::Bundler::new();
+        if self.strip_tests {
+            bundler.strip_tests();
+        }
+        bundler
+            .add_required_modules(&self.entry, &paths)
+            .with_context(|| format!("failed to bundle '{}'", self.entry))?;
+        for module in &self.publics {
+            bundler.make_public(module);
+        }
+        for unresolved in bundler.unresolved() {
+            eprintln!("{}: {unresolved}", "warning".yellow().bold());
+        }
+
+        let source = bundler.bundle(Some(&self.entry));
+        if let Some(path) = &self.out {
+            std::fs::write(path, source)?;
+        } else {
+            print!("{source}");
+        }
+        Ok(std::process::ExitCode::SUCCESS)
+    }
+}
@@ -2,44 +2,108 @@ use std::fmt;
 
 use crossterm::{style::Stylize, terminal};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
+
+/// Version of the messages, which the runner sends in [`Output::Hello`]
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long to wait for [`Output::Hello`], e.g. if the interpreter doesn't run the runner
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 pub struct Process {
-    inner: Option<std::process::Child>,
-    input: tokio::fs::File,
-    output: crate::buffer::AsyncLineReader<tokio::fs::File>,
+    inner: Inner,
+    features: Vec<Feature>,
+    /// Title of the test between its `TestStarted` and `TestFinished`
+    current_test: Option<Vec<String>>,
+}
+
+// only one exists for each profile
+#[allow(clippy::large_enum_variant)]
+enum Inner {
+    Child {
+        child: Option<tokio::process::Child>,
+        transport: crate::transport::Transport,
+        crash_report: std::path::PathBuf,
+    },
+    #[cfg(feature = "in-process")]
+    InProcess(crate::in_process::Runtime),
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error(transparent)]
     Io(#[from] std::io::Error),
-    #[error("{}", get_exit_error_message(.0))]
-    Exit(Option<i32>),
+    #[error("{}", get_exit_error_message(.0, .1))]
+    Exit(Option<i32>, Option<CrashReport>),
+    #[error("{0}")]
+    Handshake(String),
+    #[error(
+        "malformed output from the runner{}: {source}\n{line}",
+        get_protocol_error_context(.test)
+    )]
+    Protocol {
+        line: String,
+        /// Test running when the line was written
+        test: Option<Vec<String>>,
+        #[source]
+        source: serde_json::Error,
+    },
 }
 
 impl Process {
+    /// Spawns the runner and waits for its [`Output::Hello`].
     pub async fn spawn(
         app: &crate::app::App,
         profile: &crate::profile::Profile,
+    ) -> Result<Self, Error> {
+        let mut process = Self::spawn_runner(app, profile).await?;
+        process.handshake().await?;
+        Ok(process)
+    }
+
+    async fn spawn_runner(
+        app: &crate::app::App,
+        profile: &crate::profile::Profile,
     ) -> Result<Self, std::io::Error> {
+        #[cfg(feature = "in-process")]
+        if let Some(lua) = app.get_in_process_lua(profile) {
+            log::info!("running {} in process", lua.recommended_program_name());
+            let runtime = crate::in_process::Runtime::spawn(
+                lua,
+                app.get_main_script().to_path_buf(),
+                *profile.limits(),
+            )?;
+            return Ok(Self {
+                inner: Inner::InProcess(runtime),
+                features: Vec::new(),
+                current_test: None,
+            });
+        }
+
         log::trace!("spawning new process");
 
         let temp_dir = app.create_process_dir()?;
         let input_path = temp_dir.join("in.jsonl");
         let output_path = temp_dir.join("out.jsonl");
+        let crash_report = temp_dir.join("crash.json");
+        let transport =
+            crate::transport::Transport::create(profile.ipc(), &input_path, &output_path).await?;
 
         let mut cmd = profile.lua_command().clone();
-        cmd.program(app.get_lua_program(cmd.get_program())?)
-            .arg(app.get_main_script())
+        let program = app.get_lua_program(cmd.get_program())?;
+        let main_script = app.get_main_script_for(&program);
+        cmd.program(program)
+            .arg(main_script)
             .env("LUNEST_IN", &input_path)
             .env("LUNEST_OUT", &output_path)
-            .current_dir(app.root_dir());
+            .env("LUNEST_CRASH_REPORT", &crash_report)
+            .current_dir(profile.cwd());
+        for (key, val) in profile.limits().envs() {
+            cmd.env(key, val);
+        }
         log::debug!("lua command: {}", cmd.display().env(true));
 
         let child = loop {
-            match cmd
-                .build()
+            match tokio::process::Command::from(cmd.build())
                 .stdout(std::process::Stdio::piped())
                 .stderr(std::process::Stdio::piped())
                 .spawn()
@@ -58,78 +122,251 @@ impl Process {
                 }
             }
         };
-        log::info!("process spawned as {}", child.id());
+        log::info!("process spawned as {}", child.id().unwrap_or_default());
 
         Ok(Self {
-            inner: Some(child),
-            input: tokio::fs::File::options()
-                .create_new(true)
-                .append(true)
-                .open(input_path)
-                .await?,
-            output: {
-                tokio::fs::File::create(&output_path).await?;
-                tokio::fs::File::open(output_path).await?.into()
+            inner: Inner::Child {
+                child: Some(child),
+                transport,
+                crash_report,
             },
+            features: Vec::new(),
+            current_test: None,
         })
     }
 
-    pub async fn read(&mut self) -> Result<Option<Output>, std::io::Error> {
-        let output = match self.output.read_line().await? {
-            crate::buffer::Line::Ok(s) => {
-                let out = serde_json::from_str(&s).expect("failed to deserialize an output");
-                match &out {
-                    Output::Log(s) => log::info!("[log] {s}"),
-                    _ => log::debug!("output read: {out:?}"),
+    async fn handshake(&mut self) -> Result<(), Error> {
+        let wait_hello = async {
+            loop {
+                match self.read().await? {
+                    Some(Output::Hello(hello)) => return Ok(hello),
+                    Some(output) => {
+                        return Err(Error::Handshake(format!(
+                            "runner sent {output:?} before Hello, which may be from an older \
+                             version of lunest"
+                        )));
+                    }
+                    None if !self.is_running().await? => {
+                        return Err(Error::Handshake(
+                            "runner exited without sending Hello".into(),
+                        ));
+                    }
+                    None => continue,
                 }
-                Some(out)
             }
-            crate::buffer::Line::NoLF | crate::buffer::Line::Empty => None,
         };
-        Ok(output)
+        let hello = tokio::time::timeout(HANDSHAKE_TIMEOUT, wait_hello)
+            .await
+            .map_err(|_| {
+                Error::Handshake(format!(
+                    "runner didn't send Hello in {} seconds",
+                    HANDSHAKE_TIMEOUT.as_secs()
+                ))
+            })??;
+        let needs_flush = match &self.inner {
+            Inner::Child { transport, .. } => transport.blocks_reads(),
+            #[cfg(feature = "in-process")]
+            Inner::InProcess(_) => false,
+        };
+        hello.check(needs_flush)?;
+        log::info!(
+            "runner on {} supports {:?}",
+            hello.lua_version,
+            hello.features
+        );
+        self.features = hello.features;
+        Ok(())
+    }
+
+    /// Returns `true` if the runner declared `feature` in its [`Output::Hello`].
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    pub async fn read(&mut self) -> Result<Option<Output>, Error> {
+        let line = match &mut self.inner {
+            Inner::Child {
+                child: Some(child),
+                transport,
+                ..
+            } if transport.is_output_held() => tokio::select! {
+                biased;
+                line = transport.read_line() => line?,
+                status = child.wait() => {
+                    log::info!("process exited with {}", status?);
+                    transport.close_output();
+                    transport.read_line().await?
+                }
+            },
+            Inner::Child { transport, .. } => transport.read_line().await?,
+            #[cfg(feature = "in-process")]
+            Inner::InProcess(runtime) => runtime.read_line().await,
+        };
+        let Some(line) = line else {
+            return Ok(None);
+        };
+        let mut out = serde_json::from_str(&line).map_err(|source| Error::Protocol {
+            line: line.trim_end().to_string(),
+            test: self.current_test.clone(),
+            source,
+        })?;
+        match &out {
+            Output::Log(s) => log::info!("[log] {s}"),
+            _ => log::debug!("output read: {out:?}"),
+        }
+        match &mut out {
+            Output::TestStarted(t) => self.current_test = Some(t.title.clone()),
+            Output::TestFinished(t) => {
+                self.current_test = None;
+                if !self.supports(Feature::Timing) {
+                    t.duration = None;
+                }
+            }
+            _ => (),
+        }
+        Ok(Some(out))
     }
 
     pub async fn write(&mut self, input: &Input) -> Result<(), std::io::Error> {
         log::debug!("writing input: {input:?}");
         let mut json = serde_json::to_vec(input).expect("failed to serialize an input");
         json.extend(b"\n");
-        self.input.write_all(&json).await?;
+        match &mut self.inner {
+            Inner::Child { transport, .. } => transport.write_all(&json).await?,
+            #[cfg(feature = "in-process")]
+            Inner::InProcess(runtime) => runtime.write_line(json)?,
+        }
+        Ok(())
+    }
+
+    /// Makes the process send [`Output::AllInputsRead`] after it handles the inputs written so far.
+    pub async fn flush_inputs(&mut self) -> Result<(), std::io::Error> {
+        log::debug!("flushing inputs");
+        match &mut self.inner {
+            Inner::Child { transport, .. } => transport.write_all(b"\n").await?,
+            #[cfg(feature = "in-process")]
+            Inner::InProcess(runtime) => runtime.write_line(b"\n".to_vec())?,
+        }
         Ok(())
     }
 
-    pub fn is_running(&mut self) -> Result<bool, Error> {
-        let Some(inner) = &mut self.inner else {
+    pub async fn is_running(&mut self) -> Result<bool, Error> {
+        let (child, crash_report) = match &mut self.inner {
+            Inner::Child {
+                child,
+                crash_report,
+                ..
+            } => (child, crash_report),
+            #[cfg(feature = "in-process")]
+            Inner::InProcess(runtime) => {
+                return match runtime.try_wait()? {
+                    None => Ok(true),
+                    Some(0) => Ok(false),
+                    Some(code) => Err(Error::Exit(Some(code), None)),
+                };
+            }
+        };
+        let Some(inner) = child else {
             return Ok(true);
         };
         if inner.try_wait()?.is_none() {
             return Ok(true);
         }
-        let inner = self.inner.take().unwrap();
-        log::info!("process {} already exited", inner.id());
+        let inner = child.take().unwrap();
+        log::info!("process already exited");
 
-        let out = inner.wait_with_output()?;
+        let out = inner.wait_with_output().await?;
         log::debug!("stdout: {}", String::from_utf8_lossy(&out.stdout));
         log::debug!("stderr: {}", String::from_utf8_lossy(&out.stderr));
 
         match out.status.code() {
             Some(0) => Ok(false),
-            code => Err(Error::Exit(code)),
+            code => Err(Error::Exit(code, read_crash_report(crash_report))),
         }
     }
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
-        if let Some(inner) = &mut self.inner {
-            _ = inner.kill();
+        if let Inner::Child {
+            child: Some(child), ..
+        } = &mut self.inner
+        {
+            _ = child.start_kill();
         }
     }
 }
 
-fn get_exit_error_message(code: &Option<i32>) -> String {
-    match code {
+fn get_exit_error_message(code: &Option<i32>, report: &Option<CrashReport>) -> String {
+    let mut msg = match code {
         Some(n) => format!("spawned process exited with status code {n}"),
         None => "spawned process terminated by signal".into(),
+    };
+    if let Some(report) = report {
+        msg.push_str(&format!("\n{report}"));
+    }
+    msg
+}
+
+fn get_protocol_error_context(test: &Option<Vec<String>>) -> String {
+    match test {
+        Some(title) => format!(" while running '{}'", title.join(" :: ")),
+        None => String::new(),
+    }
+}
+
+fn read_crash_report(path: &std::path::Path) -> Option<CrashReport> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!("failed to read the crash report: {e}");
+            return None;
+        }
+    };
+    serde_json::from_slice(&contents)
+        .inspect_err(|e| log::warn!("failed to parse the crash report: {e}"))
+        .ok()
+}
+
+/// Uncaught error written by the interpreter to `LUNEST_CRASH_REPORT` before it exits.
+#[derive(Debug, Deserialize)]
+pub struct CrashReport {
+    pub message: String,
+    /// Call stack from the innermost frame
+    pub traceback: Vec<Frame>,
+    /// Name of the interpreter, e.g. `lua54`
+    pub version: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Frame {
+    pub source: String,
+    pub line: Option<i32>,
+    pub name: Option<String>,
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.message, self.version)?;
+        write!(f, "  stack traceback:")?;
+        for frame in &self.traceback {
+            write!(f, "\n\t{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.source)?;
+        if let Some(line) = self.line {
+            write!(f, "{line}:")?;
+        }
+        match &self.name {
+            Some(name) => write!(f, " in function '{name}'"),
+            None => write!(f, " in ?"),
+        }
     }
 }
 
@@ -139,6 +376,9 @@ pub enum Input {
     Initialize {
         target_files: Vec<TargetFile>,
         root_dir: std::path::PathBuf,
+        cwd: std::path::PathBuf,
+        path: Vec<String>,
+        cpath: Vec<String>,
         term_width: u16,
     },
     Run {
@@ -177,6 +417,49 @@ pub enum Output {
     TestFinished(TestFinished),
     AllInputsRead,
     Log(String),
+    Hello(Hello),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Hello {
+    pub protocol: u32,
+    /// `_VERSION`, or `jit.version` on LuaJIT
+    pub lua_version: String,
+    pub features: Vec<Feature>,
+}
+
+impl Hello {
+    /// Returns an error if lunest can't talk with the runner, where `needs_flush` is `true` if
+    /// the runner can't tell that all inputs are read without [`Feature::Flush`].
+    fn check(&self, needs_flush: bool) -> Result<(), Error> {
+        if self.protocol != PROTOCOL_VERSION {
+            return Err(Error::Handshake(format!(
+                "runner uses protocol version {}, but lunest {} supports version {PROTOCOL_VERSION}",
+                self.protocol,
+                env!("CARGO_PKG_VERSION"),
+            )));
+        }
+        if needs_flush && !self.features.contains(&Feature::Flush) {
+            return Err(Error::Handshake(
+                "runner doesn't support the 'flush' feature, which is needed to use named pipes; \
+                 set `ipc = \"file\"` in the profile"
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// [`TestFinished`] has the duration of the test
+    Timing,
+    /// Empty input lines are answered with [`Output::AllInputsRead`]
+    Flush,
+    /// Sent by a newer runner
+    #[serde(other)]
+    Unknown,
 }
 
 fn fmt_title(title: &[String]) -> String {
@@ -187,6 +470,24 @@ fn fmt_title(title: &[String]) -> String {
 pub struct TestInfo {
     pub id: String,
     pub title: Vec<String>,
+    pub source: std::path::PathBuf,
+    /// Lines where the enclosing groups and the test are defined, from the outermost one
+    pub line_ranges: Vec<LineRange>,
+    /// Whether the last one of `line_ranges` is where the test is defined
+    pub has_line_range: bool,
+}
+
+impl TestInfo {
+    /// Returns the lines where the test is defined.
+    pub fn line_range(&self) -> Option<&LineRange> {
+        self.line_ranges.last().filter(|_| self.has_line_range)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct LineRange {
+    pub first: u32,
+    pub last: u32,
 }
 
 impl fmt::Display for TestInfo {
@@ -216,23 +517,65 @@ impl fmt::Display for TestStarted {
 pub struct TestFinished {
     title: Vec<String>,
     error: Option<TestError>,
+    /// CPU time in seconds
+    duration: Option<f64>,
 }
 
 impl TestFinished {
+    /// Result of a test that wrote a malformed line, if it never finished properly.
+    pub fn from_error(title: Vec<String>, err: &Error) -> Self {
+        let mut result = Self {
+            title,
+            error: None,
+            duration: None,
+        };
+        result.fail_with(err);
+        result
+    }
+
+    pub fn title(&self) -> &[String] {
+        &self.title
+    }
+
     pub fn success(&self) -> bool {
         self.error.is_none()
     }
+
+    /// Makes the test fail with `err` unless it already failed.
+    pub fn fail_with(&mut self, err: &Error) {
+        if self.error.is_none() {
+            self.error = Some(TestError {
+                message: err.to_string(),
+                traceback: String::new(),
+                info: None,
+            });
+        }
+    }
 }
 
 impl fmt::Display for TestFinished {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", terminal::Clear(terminal::ClearType::UntilNewLine))?;
         write!(f, "{}{} ", fmt_title(&self.title), ":".grey())?;
+        match &self.error {
+            Some(_) => write!(f, "{}", "ERR".red().bold())?,
+            None => write!(f, "{}", "OK".green().bold())?,
+        }
+        if let Some(duration) = self.duration {
+            write!(f, " {}", format!("({})", fmt_duration(duration)).grey())?;
+        }
         if let Some(err) = &self.error {
-            write!(f, "{}\n{}", "ERR".red().bold(), err)
-        } else {
-            write!(f, "{}", "OK".green().bold())
+            write!(f, "\n{err}")?;
         }
+        Ok(())
+    }
+}
+
+fn fmt_duration(seconds: f64) -> String {
+    if seconds < 1.0 {
+        format!("{:.0}ms", seconds * 1000.0)
+    } else {
+        format!("{seconds:.2}s")
     }
 }
 
@@ -254,8 +597,10 @@ impl fmt::Display for TestError {
         if let Some(info) = &self.info {
             writeln!(f, "{info}")?;
         }
-        writeln!(f, "{}:", "  stack traceback".bold())?;
-        writeln!(f, "{}", self.traceback)?;
+        if !self.traceback.is_empty() {
+            writeln!(f, "{}:", "  stack traceback".bold())?;
+            writeln!(f, "{}", self.traceback)?;
+        }
         Ok(())
     }
 }
@@ -292,3 +637,101 @@ impl fmt::Display for TestErrorInfo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod crash_report_tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let report: CrashReport = serde_json::from_str(
+            r#"{
+                "message": "main.lua:3: oops",
+                "traceback": [
+                    {"source": "[C]", "line": null, "name": "error"},
+                    {"source": "main.lua", "line": 3, "name": null}
+                ],
+                "version": "lua54"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            "spawned process exited with status code 1\n\
+             main.lua:3: oops (lua54)\n  \
+             stack traceback:\n\
+             \t[C]: in function 'error'\n\
+             \tmain.lua:3: in ?",
+            Error::Exit(Some(1), Some(report)).to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod hello_tests {
+    use super::*;
+
+    #[test]
+    fn deserialize() {
+        let output: Output = serde_json::from_str(
+            r#"{"t":"Hello","c":{"protocol":1,"lua_version":"Lua 5.4","features":["timing","new"]}}"#,
+        )
+        .unwrap();
+        let Output::Hello(hello) = output else {
+            panic!("unexpected output: {output:?}");
+        };
+        assert_eq!(PROTOCOL_VERSION, hello.protocol);
+        assert_eq!(
+            [Feature::Timing, Feature::Unknown],
+            hello.features.as_slice()
+        );
+    }
+
+    #[test]
+    fn check() {
+        let hello = |protocol, features| Hello {
+            protocol,
+            lua_version: "Lua 5.4".into(),
+            features,
+        };
+        assert!(
+            hello(PROTOCOL_VERSION, vec![Feature::Flush])
+                .check(true)
+                .is_ok()
+        );
+        assert!(hello(PROTOCOL_VERSION, Vec::new()).check(false).is_ok());
+        assert_eq!(
+            "runner doesn't support the 'flush' feature, which is needed to use named pipes; \
+             set `ipc = \"file\"` in the profile",
+            hello(PROTOCOL_VERSION, Vec::new())
+                .check(true)
+                .unwrap_err()
+                .to_string(),
+        );
+        assert!(hello(0, vec![Feature::Flush]).check(false).is_err());
+    }
+}
+
+#[cfg(test)]
+mod protocol_error_tests {
+    use super::*;
+
+    #[test]
+    fn from_error() {
+        let line = "hello";
+        let source = serde_json::from_str::<Output>(line).unwrap_err();
+        let err = Error::Protocol {
+            line: line.into(),
+            test: Some(vec!["a.lua".into(), "test".into()]),
+            source,
+        };
+        assert!(
+            err.to_string()
+                .starts_with("malformed output from the runner while running 'a.lua :: test': ")
+        );
+        assert!(err.to_string().ends_with("\nhello"));
+
+        let result = TestFinished::from_error(vec!["a.lua".into(), "test".into()], &err);
+        assert!(!result.success());
+        assert_eq!(["a.lua", "test"], result.title());
+    }
+}
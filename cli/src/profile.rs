@@ -6,6 +6,11 @@ pub struct Profile {
     init_script: Option<std::path::PathBuf>,
     target_files: Vec<std::path::PathBuf>,
     lua_command: crate::command::Builder,
+    cwd: std::path::PathBuf,
+    package_path: Vec<String>,
+    package_cpath: Vec<String>,
+    limits: lua_rt::Limits,
+    ipc: crate::transport::Ipc,
 }
 
 impl Profile {
@@ -21,10 +26,35 @@ impl Profile {
         &self.target_files
     }
 
+    pub fn retain_target_files(&mut self, mut f: impl FnMut(&std::path::Path) -> bool) {
+        self.target_files.retain(|p| f(p));
+    }
+
     pub fn lua_command(&self) -> &crate::command::Builder {
         &self.lua_command
     }
 
+    pub fn cwd(&self) -> &std::path::Path {
+        &self.cwd
+    }
+
+    pub fn package_path(&self) -> &[String] {
+        &self.package_path
+    }
+
+    pub fn package_cpath(&self) -> &[String] {
+        &self.package_cpath
+    }
+
+    pub fn ipc(&self) -> crate::transport::Ipc {
+        self.ipc
+    }
+
+    /// Limits applied if the embedded interpreter is used.
+    pub fn limits(&self) -> &lua_rt::Limits {
+        &self.limits
+    }
+
     pub fn from_spec(
         name: String,
         spec: crate::profile::Specifier,
@@ -36,14 +66,47 @@ impl Profile {
             }
             std::fs::canonicalize(&path).unwrap_or(path)
         });
-        let target_files = target_files(
-            root_dir,
-            &GlobSet::new(
-                spec.include.unwrap_or_default().as_slice(),
-                spec.exclude.unwrap_or_default().as_slice(),
-            )?,
-            init_script.as_ref(),
-        )?;
+        let include = spec.include.unwrap_or_default();
+        let exclude = spec.exclude.unwrap_or_default();
+        let globset = GlobSet::new(&patterns(&include), &patterns(&exclude)).map_err(|e| {
+            let (pattern, source) = match e {
+                GlobError::Include(i, source) => (&include[i], source),
+                GlobError::Exclude(i, source) => (&exclude[i], source),
+                GlobError::Build(source) => return anyhow::Error::from(source),
+            };
+            if pattern.span().is_empty() {
+                // not written in the configuration file
+                return anyhow::anyhow!("invalid glob pattern '{pattern}': {}", source.kind());
+            }
+            crate::config::SpannedError::new(
+                pattern.span(),
+                format!("invalid glob pattern: {}", source.kind()),
+            )
+            .into()
+        })?;
+        let target_files = target_files(root_dir, &globset, init_script.as_ref())?;
+        let cwd = match spec.cwd {
+            Some(mut path) => {
+                if path.is_relative() {
+                    path = root_dir.join(path);
+                }
+                anyhow::ensure!(path.is_dir(), "'cwd' directory {path:?} does not exist");
+                std::fs::canonicalize(&path).unwrap_or(path)
+            }
+            None => root_dir.to_path_buf(),
+        };
+        let package_path = spec
+            .path
+            .unwrap_or_default()
+            .iter()
+            .map(|t| resolve_template(t, root_dir))
+            .collect();
+        let package_cpath = spec
+            .cpath
+            .unwrap_or_default()
+            .iter()
+            .map(|t| resolve_template(t, root_dir))
+            .collect();
         Ok(Self {
             name,
             init_script,
@@ -54,12 +117,57 @@ impl Profile {
                     lua.first().context("'lua' field must not be empty")?,
                 );
                 cmd.args(lua.get(1..).unwrap_or_default());
+                if spec.env_clear.unwrap_or(false) {
+                    cmd.env_clear();
+                    for key in spec.inherit_env.unwrap_or_default() {
+                        if let Some(val) = std::env::var_os(&key) {
+                            cmd.env(key, val);
+                        }
+                    }
+                }
+                for (key, val) in spec.env.unwrap_or_default() {
+                    cmd.env(key, val);
+                }
                 cmd
             },
+            cwd,
+            package_path,
+            package_cpath,
+            limits: lua_rt::Limits {
+                memory: spec.memory_limit.unwrap_or(0),
+                instructions: spec.instruction_limit.unwrap_or(0),
+            },
+            ipc: spec
+                .ipc
+                .unwrap_or_else(|| default_ipc(spec.lua.as_deref().unwrap_or_default())),
         })
     }
 }
 
+/// Uses named pipes only for the interpreters that lunest embeds, since the others may not be
+/// able to read them, e.g. `nvim -l`.
+fn default_ipc(lua: &[String]) -> crate::transport::Ipc {
+    match lua.first() {
+        Some(program) if lua_rt::Lua::from_program_name(program).is_some() => {
+            crate::transport::Ipc::Pipe
+        }
+        _ => crate::transport::Ipc::File,
+    }
+}
+
+/// Makes a template for `package.path` or `package.cpath` absolute.
+fn resolve_template(template: &str, root_dir: &std::path::Path) -> String {
+    if std::path::Path::new(template).is_relative() {
+        root_dir.join(template).display().to_string()
+    } else {
+        template.to_string()
+    }
+}
+
+fn patterns(patterns: &[toml::Spanned<String>]) -> Vec<String> {
+    patterns.iter().map(|p| p.get_ref().clone()).collect()
+}
+
 #[derive(Debug)]
 struct GlobSet {
     included_dirs: globset::GlobSet,
@@ -67,14 +175,24 @@ struct GlobSet {
     excluded_files: globset::GlobSet,
 }
 
+#[derive(thiserror::Error, Debug)]
+enum GlobError {
+    #[error("invalid pattern at index {0} of 'include': {1}")]
+    Include(usize, #[source] globset::Error),
+    #[error("invalid pattern at index {0} of 'exclude': {1}")]
+    Exclude(usize, #[source] globset::Error),
+    #[error(transparent)]
+    Build(#[from] globset::Error),
+}
+
 impl GlobSet {
-    fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+    fn new(include: &[String], exclude: &[String]) -> Result<Self, GlobError> {
         let mut included_dirs = globset::GlobSetBuilder::new();
         let mut included_files = globset::GlobSetBuilder::new();
         let mut pattern_set = std::collections::HashSet::new();
-        for file_pattern in include {
+        for (i, file_pattern) in include.iter().enumerate() {
             if !pattern_set.contains(file_pattern) {
-                included_files.add(new_glob(file_pattern)?);
+                included_files.add(new_glob(file_pattern).map_err(|e| GlobError::Include(i, e))?);
                 pattern_set.insert(file_pattern.to_string());
             }
             for slash_index in file_pattern
@@ -83,14 +201,14 @@ impl GlobSet {
             {
                 let dir_pattern = file_pattern.get(..slash_index).unwrap();
                 if !pattern_set.contains(dir_pattern) {
-                    included_dirs.add(new_glob(dir_pattern)?);
+                    included_dirs.add(new_glob(dir_pattern).map_err(|e| GlobError::Include(i, e))?);
                     pattern_set.insert(dir_pattern.to_string());
                 }
             }
         }
         let mut excluded_files = globset::GlobSetBuilder::new();
-        for file_pattern in exclude {
-            excluded_files.add(new_glob(file_pattern)?);
+        for (i, file_pattern) in exclude.iter().enumerate() {
+            excluded_files.add(new_glob(file_pattern).map_err(|e| GlobError::Exclude(i, e))?);
         }
         Ok(Self {
             included_dirs: included_dirs.build()?,
@@ -147,6 +265,18 @@ mod globset_tests {
         assert!(p.is_match(std::path::Path::new("a/b/c.lua"), false));
     }
 
+    #[test]
+    fn error_index() {
+        assert!(matches!(
+            GlobSet::new(&["a/*.lua".into(), "b/[c/*.lua".into()], &[]),
+            Err(GlobError::Include(1, _)),
+        ));
+        assert!(matches!(
+            GlobSet::new(&["**/*.lua".into()], &["a/*.lua".into(), "{b".into()]),
+            Err(GlobError::Exclude(1, _)),
+        ));
+    }
+
     #[test]
     fn match_directory() {
         let p = GlobSet::new(&["a/**/*.lua".into()], &["a*/**/*.lua".into()]).unwrap();
@@ -188,24 +318,95 @@ fn target_files(
 }
 
 #[derive(Clone, Debug, serde::Deserialize, merge::Merge)]
+#[serde(deny_unknown_fields)]
 pub struct Specifier {
+    /// Name of the profile to inherit the settings from
+    #[merge(skip)]
+    pub extends: Option<toml::Spanned<String>>,
     #[merge(strategy = merge::option::overwrite_none)]
     pub lua: Option<Vec<String>>,
     #[merge(strategy = merge::option::overwrite_none)]
-    pub include: Option<Vec<String>>,
+    pub include: Option<Vec<toml::Spanned<String>>>,
     #[merge(strategy = merge::option::overwrite_none)]
-    pub exclude: Option<Vec<String>>,
+    pub exclude: Option<Vec<toml::Spanned<String>>>,
     #[merge(strategy = merge::option::overwrite_none)]
     pub init: Option<std::path::PathBuf>,
+    /// Templates prepended to `package.path`, relative to the root directory
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub path: Option<Vec<String>>,
+    /// Templates prepended to `package.cpath`, relative to the root directory
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub cpath: Option<Vec<String>>,
+    /// Environment variables set for the Lua process
+    #[merge(strategy = merge_env)]
+    pub env: Option<std::collections::HashMap<String, String>>,
+    /// Don't inherit environment variables except the ones in `inherit_env`
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub env_clear: Option<bool>,
+    /// Environment variables inherited even if `env_clear` is enabled
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub inherit_env: Option<Vec<String>>,
+    /// Working directory of the Lua process, relative to the root directory
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub cwd: Option<std::path::PathBuf>,
+    /// Maximum bytes allocated by the embedded interpreter
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub memory_limit: Option<u64>,
+    /// Maximum number of instructions executed by each test in the embedded interpreter
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub instruction_limit: Option<u64>,
+    /// `pipe` to exchange the messages through named pipes, or `file` for interpreters that can't
+    /// open them. Defaults to `pipe` for the interpreters embedded in lunest, e.g. `lua5.4`, and
+    /// to `file` for the others
+    #[merge(strategy = merge::option::overwrite_none)]
+    pub ipc: Option<crate::transport::Ipc>,
+}
+
+/// Merges the variables key by key so that a profile can override some of the defaults.
+fn merge_env(
+    left: &mut Option<std::collections::HashMap<String, String>>,
+    right: Option<std::collections::HashMap<String, String>>,
+) {
+    match (left, right) {
+        (Some(left), Some(right)) => {
+            for (key, val) in right {
+                left.entry(key).or_insert(val);
+            }
+        }
+        (left @ None, right) => *left = right,
+        (Some(_), None) => (),
+    }
+}
+
+impl Specifier {
+    /// Returns a specifier with no fields set, unlike [`Specifier::default`].
+    pub fn empty() -> Self {
+        Self {
+            extends: None,
+            lua: None,
+            include: None,
+            exclude: None,
+            init: None,
+            path: None,
+            cpath: None,
+            env: None,
+            env_clear: None,
+            inherit_env: None,
+            cwd: None,
+            memory_limit: None,
+            instruction_limit: None,
+            ipc: None,
+        }
+    }
 }
 
 impl Default for Specifier {
     fn default() -> Self {
         Self {
             lua: Some(vec!["lua".into()]),
-            include: Some(vec!["{src,lua}/**/*.lua".into()]),
+            include: Some(vec![toml::Spanned::new(0..0, "{src,lua}/**/*.lua".into())]),
             exclude: Some(vec![]),
-            init: None,
+            ..Self::empty()
         }
     }
 }
@@ -214,6 +415,7 @@ impl Default for Specifier {
 mod tests {
     use super::*;
 
+    use merge::Merge as _;
     use rstest::{fixture, rstest};
 
     #[fixture]
@@ -258,6 +460,110 @@ mod tests {
         assert!(crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).is_err());
     }
 
+    #[rstest]
+    #[case(crate::transport::Ipc::Pipe, &["lua"], None)]
+    #[case(crate::transport::Ipc::File, &["nvim", "-l"], None)]
+    #[case(crate::transport::Ipc::File, &["lua"], Some(crate::transport::Ipc::File))]
+    #[case(
+        crate::transport::Ipc::Pipe,
+        &["nvim", "-l"],
+        Some(crate::transport::Ipc::Pipe)
+    )]
+    fn ipc(
+        root_dir: tempfile::TempDir,
+        #[case] expected: crate::transport::Ipc,
+        #[case] lua: &[&str],
+        #[case] spec_ipc: Option<crate::transport::Ipc>,
+    ) {
+        let spec = crate::profile::Specifier {
+            lua: Some(lua.iter().map(|s| s.to_string()).collect()),
+            ipc: spec_ipc,
+            ..Default::default()
+        };
+        let p = crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).unwrap();
+        assert_eq!(expected, p.ipc());
+    }
+
+    #[rstest]
+    fn env(root_dir: tempfile::TempDir) {
+        let mut spec = crate::profile::Specifier {
+            lua: Some(vec!["lua".into()]),
+            env: Some([("TZ".into(), "UTC".into())].into()),
+            ..Default::default()
+        };
+        spec.merge(crate::profile::Specifier {
+            env: Some([("TZ".into(), "JST".into()), ("A".into(), "a".into())].into()),
+            env_clear: Some(true),
+            ..Default::default()
+        });
+        let p = crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).unwrap();
+        assert_eq!(
+            crate::command::Builder::new("lua")
+                .env_clear()
+                .env("TZ", "UTC")
+                .env("A", "a")
+                .clone(),
+            p.lua_command
+        );
+    }
+
+    #[rstest]
+    fn cwd(root_dir: tempfile::TempDir) {
+        let spec = crate::profile::Specifier {
+            cwd: Some("test".into()),
+            ..Default::default()
+        };
+        let p = crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).unwrap();
+        assert_eq!(
+            std::fs::canonicalize(root_dir.path().join("test")).unwrap(),
+            p.cwd
+        );
+
+        let spec = crate::profile::Specifier {
+            cwd: Some("none".into()),
+            ..Default::default()
+        };
+        assert!(crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).is_err());
+    }
+
+    #[rstest]
+    fn package_path(root_dir: tempfile::TempDir) {
+        let absolute = root_dir.path().join("share/?.lua").display().to_string();
+        let spec = crate::profile::Specifier {
+            path: Some(vec!["lua/?.lua".into(), absolute.clone()]),
+            ..Default::default()
+        };
+        let p = crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).unwrap();
+        assert_eq!(
+            vec![
+                root_dir.path().join("lua/?.lua").display().to_string(),
+                absolute,
+            ],
+            p.package_path
+        );
+        assert!(p.package_cpath.is_empty());
+    }
+
+    #[rstest]
+    fn limits(root_dir: tempfile::TempDir) {
+        let spec = crate::profile::Specifier {
+            memory_limit: Some(1 << 30),
+            ..Default::default()
+        };
+        let p = crate::profile::Profile::from_spec("name".into(), spec, root_dir.path()).unwrap();
+        assert_eq!(
+            lua_rt::Limits {
+                memory: 1 << 30,
+                instructions: 0,
+            },
+            p.limits
+        );
+        assert_eq!(
+            vec![("LUNEST_MEMORY_LIMIT", (1u64 << 30).to_string())],
+            p.limits.envs()
+        );
+    }
+
     #[rstest]
     fn include_and_exclude(root_dir: tempfile::TempDir) -> anyhow::Result<()> {
         let root = root_dir.path();
@@ -0,0 +1,179 @@
+use tokio::io::AsyncWriteExt;
+
+/// How lunest and the Lua process exchange the messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ipc {
+    /// Named pipes, or files where they are not supported
+    Pipe,
+    /// Files polled by both sides, for interpreters that can't open named pipes
+    File,
+}
+
+/// Input and output of the Lua process, passed to it as `LUNEST_IN` and `LUNEST_OUT`.
+pub enum Transport {
+    File {
+        input: tokio::fs::File,
+        output: crate::buffer::AsyncLineReader<tokio::fs::File>,
+    },
+    /// Reads of both sides wait until a line is written.
+    #[cfg(unix)]
+    Pipe {
+        input: tokio::net::unix::pipe::Sender,
+        output: crate::buffer::AsyncLineReader<tokio::net::unix::pipe::Receiver>,
+        /// Keeps `output` from reaching EOF, which isn't notified if the Lua process exits
+        /// without opening it, until [`Transport::close_output`]
+        output_writer: Option<std::fs::File>,
+    },
+}
+
+impl Transport {
+    /// Creates the files or the pipes before the Lua process opens them.
+    pub async fn create(
+        ipc: Ipc,
+        input_path: &std::path::Path,
+        output_path: &std::path::Path,
+    ) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        if ipc == Ipc::Pipe {
+            mkfifo(input_path)?;
+            mkfifo(output_path)?;
+            // opened for reading too, so that it doesn't fail until the Lua process opens it
+            let input = std::fs::File::options()
+                .read(true)
+                .write(true)
+                .open(input_path)?;
+            let output = tokio::net::unix::pipe::OpenOptions::new().open_receiver(output_path)?;
+            return Ok(Self::Pipe {
+                input: tokio::net::unix::pipe::Sender::from_file(input)?,
+                output: output.into(),
+                output_writer: Some(std::fs::File::options().write(true).open(output_path)?),
+            });
+        }
+        #[cfg(not(unix))]
+        let _ = ipc;
+
+        Ok(Self::File {
+            input: tokio::fs::File::options()
+                .create_new(true)
+                .append(true)
+                .open(input_path)
+                .await?,
+            output: {
+                tokio::fs::File::create(output_path).await?;
+                tokio::fs::File::open(output_path).await?.into()
+            },
+        })
+    }
+
+    /// Returns `None` if no complete line is written yet, or if the pipe is closed by both the Lua
+    /// process and [`Transport::close_output`]. Cancel safe.
+    pub async fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let line = match self {
+            Self::File { output, .. } => output.read_line().await?,
+            #[cfg(unix)]
+            Self::Pipe { output, .. } => output.read_line().await?,
+        };
+        Ok(match line {
+            crate::buffer::Line::Ok(s) => Some(s),
+            crate::buffer::Line::NoLF | crate::buffer::Line::Empty => None,
+        })
+    }
+
+    /// Returns `true` if reads of the Lua process wait until a line is written.
+    pub fn blocks_reads(&self) -> bool {
+        match self {
+            Self::File { .. } => false,
+            #[cfg(unix)]
+            Self::Pipe { .. } => true,
+        }
+    }
+
+    /// Returns `true` if reads wait for the Lua process until [`Transport::close_output`].
+    pub fn is_output_held(&self) -> bool {
+        match self {
+            Self::File { .. } => false,
+            #[cfg(unix)]
+            Self::Pipe { output_writer, .. } => output_writer.is_some(),
+        }
+    }
+
+    /// Lets reads return `None` after the lines left by the Lua process, which must have exited.
+    pub fn close_output(&mut self) {
+        #[cfg(unix)]
+        if let Self::Pipe { output_writer, .. } = self {
+            *output_writer = None;
+        }
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::File { input, .. } => input.write_all(buf).await,
+            #[cfg(unix)]
+            Self::Pipe { input, .. } => input.write_all(buf).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn mkfifo(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string.
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod transport_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pipe() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("in");
+        let output_path = dir.path().join("out");
+        let mut transport = Transport::create(Ipc::Pipe, &input_path, &output_path)
+            .await
+            .unwrap();
+
+        // the other side
+        let thread = std::thread::spawn(move || {
+            use std::io::{BufRead as _, Write as _};
+
+            let mut input = std::io::BufReader::new(std::fs::File::open(input_path).unwrap());
+            let mut output = std::fs::File::options()
+                .append(true)
+                .open(output_path)
+                .unwrap();
+            let mut line = String::new();
+            input.read_line(&mut line).unwrap();
+            output.write_all(line.to_uppercase().as_bytes()).unwrap();
+        });
+
+        transport.write_all(b"hello\n").await.unwrap();
+        assert_eq!(
+            Some("HELLO\n"),
+            transport.read_line().await.unwrap().as_deref(),
+        );
+        thread.join().unwrap();
+        transport.close_output();
+        assert_eq!(None, transport.read_line().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pipe_never_opened() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut transport =
+            Transport::create(Ipc::Pipe, &dir.path().join("in"), &dir.path().join("out"))
+                .await
+                .unwrap();
+        transport.close_output();
+        let line = tokio::time::timeout(std::time::Duration::from_secs(10), transport.read_line());
+        assert_eq!(None, line.await.unwrap().unwrap());
+    }
+}
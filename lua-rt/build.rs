@@ -1,12 +1,24 @@
+#[path = "hash.rs"]
+mod hash;
+
 const ZSTD_COMPRESSION_LEVEL: i32 = if cfg!(debug_assertions) { 3 } else { 22 };
 
 fn main() -> std::io::Result<()> {
     let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
-    let versions = ["lua54", "lua53", "lua52", "lua51"];
+    let versions: Vec<_> = ["lua54", "lua53", "lua52", "lua51", "luajit"]
+        .into_iter()
+        .filter(|v| std::env::var_os(format!("CARGO_FEATURE_{}", v.to_uppercase())).is_some())
+        .collect();
+    let checked = std::env::var_os("CARGO_FEATURE_CHECKED").is_some();
+    let in_process = std::env::var_os("CARGO_FEATURE_IN_PROCESS").is_some();
 
     println!("cargo::rerun-if-changed=../build.zig");
     println!("cargo::rerun-if-changed=../build.zig.zon");
     println!("cargo::rerun-if-changed=./main.zig");
+    println!("cargo::rerun-if-changed=./lib.zig");
+    println!("cargo::rerun-if-changed=./limit.zig");
+    println!("cargo::rerun-if-changed=./bundled.zig");
+    println!("cargo::rerun-if-changed=./hash.rs");
 
     let mut c = std::process::Command::new("zig");
     c.arg("build");
@@ -31,11 +43,41 @@ fn main() -> std::io::Result<()> {
     };
     eprintln!("zig optimize mode: {optimize}");
     c.arg(optimize);
+    for version in &versions {
+        c.arg(format!("-Dlang={version}"));
+    }
+    if checked {
+        c.arg("-Dchecked");
+    }
+    if in_process {
+        c.arg("-Din-process");
+    }
+    for lib in ["lpeg", "lfs"] {
+        let feature = format!("CARGO_FEATURE_{}", lib.to_uppercase());
+        if std::env::var_os(feature).is_some() {
+            c.arg(format!("-D{lib}"));
+        }
+    }
     assert!(c.status()?.success());
 
+    if in_process {
+        for version in &versions {
+            prefix_libraries(version, &out_dir)?;
+        }
+        println!("cargo::rustc-link-search=native={}", out_dir.display());
+        if std::env::var("CARGO_CFG_TARGET_OS").unwrap() == "linux" {
+            println!("cargo::rustc-link-lib=m");
+            println!("cargo::rustc-link-lib=dl");
+        }
+    }
+
     let mut artifacts = Vec::new();
     let bin_dir = std::path::PathBuf::from("../zig-out/bin");
-    for version in versions {
+    let mut names: Vec<_> = versions.iter().map(|v| v.to_string()).collect();
+    if checked {
+        names.extend(versions.iter().map(|v| format!("{v}-checked")));
+    }
+    for version in names {
         let bin_name = format!("{version}{}", std::env::consts::EXE_SUFFIX);
         let contents = std::fs::read(bin_dir.join(bin_name))?;
         artifacts.push((version, contents));
@@ -47,6 +89,10 @@ fn main() -> std::io::Result<()> {
             out_dir.join(format!("{version}_size.rs")),
             format!("{}", contents.len()),
         )?;
+        std::fs::write(
+            out_dir.join(format!("{version}_hash.rs")),
+            format!("{:#x}", hash::fnv1a(&contents)),
+        )?;
         let out = out_dir.join(format!("{version}.zst"));
         threads.push(std::thread::spawn(move || -> std::io::Result<()> {
             eprintln!("compressing {version}...");
@@ -67,3 +113,63 @@ fn main() -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// Copies the static libraries of `version` into `out_dir` with their global symbols prefixed by
+/// `{version}_`, so that the libraries of all versions can be linked into one binary.
+fn prefix_libraries(version: &str, out_dir: &std::path::Path) -> std::io::Result<()> {
+    let tool = |var: &str, default: &str| std::env::var_os(var).unwrap_or(default.into());
+    let symbol_prefix = if std::env::var("CARGO_CFG_TARGET_VENDOR").unwrap() == "apple" {
+        "_"
+    } else {
+        ""
+    };
+
+    let lib_dir = std::path::Path::new("../zig-out/lib").join(version);
+    let mut libs = Vec::new();
+    for entry in std::fs::read_dir(&lib_dir)? {
+        libs.push(entry?.path());
+    }
+
+    let mut symbols = std::collections::BTreeSet::new();
+    for lib in &libs {
+        let out = std::process::Command::new(tool("NM", "nm"))
+            .args(["--defined-only", "--extern-only", "--format=posix"])
+            .arg(lib)
+            .output()?;
+        assert!(out.status.success(), "failed to list symbols of {lib:?}");
+        for line in String::from_utf8_lossy(&out.stdout).lines() {
+            // skip the headers of archive members
+            if line.ends_with(':') {
+                continue;
+            }
+            if let Some((name, _)) = line.split_once(' ') {
+                symbols.insert(name.to_string());
+            }
+        }
+    }
+    let map = out_dir.join(format!("{version}.syms"));
+    let mut contents = String::new();
+    for name in &symbols {
+        let base = name.strip_prefix(symbol_prefix).unwrap_or(name);
+        contents.push_str(&format!("{name} {symbol_prefix}{version}_{base}\n"));
+    }
+    std::fs::write(&map, contents)?;
+
+    for lib in &libs {
+        let stem = lib.file_stem().unwrap().to_string_lossy();
+        let name = format!("{version}_{}", stem.strip_prefix("lib").unwrap_or(&stem));
+        let file_name = if std::env::var("CARGO_CFG_TARGET_ENV").unwrap() == "msvc" {
+            format!("{name}.lib")
+        } else {
+            format!("lib{name}.a")
+        };
+        let status = std::process::Command::new(tool("OBJCOPY", "objcopy"))
+            .arg(format!("--redefine-syms={}", map.display()))
+            .arg(lib)
+            .arg(out_dir.join(file_name))
+            .status()?;
+        assert!(status.success(), "failed to prefix symbols of {lib:?}");
+        println!("cargo::rustc-link-lib=static={name}");
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! C libraries compiled into the interpreter by `build.zig`.
+
+const ziglua = @import("ziglua");
+const Lua = ziglua.Lua;
+
+const options = @import("bundled");
+
+extern fn luaopen_lpeg(state: ?*ziglua.LuaState) callconv(.c) c_int;
+extern fn luaopen_lfs(state: ?*ziglua.LuaState) callconv(.c) c_int;
+
+/// Registers the libraries in `package.preload`.
+pub fn preload(lua: *Lua) !void {
+    _ = try lua.getGlobal("package");
+    _ = lua.getField(-1, "preload");
+    if (options.lpeg) {
+        lua.pushFunction(&luaopen_lpeg);
+        lua.setField(-2, "lpeg");
+    }
+    if (options.lfs) {
+        lua.pushFunction(&luaopen_lfs);
+        lua.setField(-2, "lfs");
+    }
+    lua.pop(2);
+}
@@ -0,0 +1,231 @@
+//! Runs the embedded interpreters as libraries in the current process.
+
+use std::ffi::{CString, c_char, c_int, c_void};
+
+use crate::Limits;
+
+/// Lines exchanged between the script and the host.
+pub trait Host {
+    /// Returns the next line sent to the script, or `None` if there is no input for now.
+    fn read(&mut self) -> Option<Vec<u8>>;
+    fn write(&mut self, line: &[u8]);
+}
+
+#[repr(C)]
+struct RawHost {
+    ctx: *mut c_void,
+    read: unsafe extern "C" fn(*mut c_void, *mut usize) -> *const u8,
+    write: unsafe extern "C" fn(*mut c_void, *const u8, usize),
+}
+
+struct Context<'a> {
+    host: &'a mut dyn Host,
+    /// Keeps the line returned by `read` alive until the next call.
+    line: Vec<u8>,
+}
+
+unsafe extern "C" fn read(ctx: *mut c_void, len: *mut usize) -> *const u8 {
+    let cx = unsafe { &mut *ctx.cast::<Context>() };
+    match cx.host.read() {
+        Some(line) => {
+            cx.line = line;
+            unsafe { *len = cx.line.len() };
+            cx.line.as_ptr()
+        }
+        None => std::ptr::null(),
+    }
+}
+
+unsafe extern "C" fn write(ctx: *mut c_void, ptr: *const u8, len: usize) {
+    let cx = unsafe { &mut *ctx.cast::<Context>() };
+    cx.host
+        .write(unsafe { std::slice::from_raw_parts(ptr, len) });
+}
+
+// defined in `lib.zig` and prefixed by `build.rs`
+unsafe extern "C" {
+    #[cfg(feature = "lua51")]
+    fn lua51_lunest_run(
+        script: *const c_char,
+        limits: *const Limits,
+        host: *const RawHost,
+    ) -> c_int;
+    #[cfg(feature = "lua52")]
+    fn lua52_lunest_run(
+        script: *const c_char,
+        limits: *const Limits,
+        host: *const RawHost,
+    ) -> c_int;
+    #[cfg(feature = "lua53")]
+    fn lua53_lunest_run(
+        script: *const c_char,
+        limits: *const Limits,
+        host: *const RawHost,
+    ) -> c_int;
+    #[cfg(feature = "lua54")]
+    fn lua54_lunest_run(
+        script: *const c_char,
+        limits: *const Limits,
+        host: *const RawHost,
+    ) -> c_int;
+    #[cfg(feature = "luajit")]
+    fn luajit_lunest_run(
+        script: *const c_char,
+        limits: *const Limits,
+        host: *const RawHost,
+    ) -> c_int;
+}
+
+impl crate::Lua {
+    /// Runs `script` on the current thread and returns the exit status.
+    ///
+    /// The script can access `host` through the `LUNEST_HOST` global, which has `read()` and
+    /// `write(line)` functions. Fails without running the script if the interpreter isn't
+    /// linked as a library, e.g. if it's built with API checks.
+    ///
+    /// `os.exit` raises an error instead of exiting the current process. The standard streams
+    /// are the ones of the current process, so the script should not write to them.
+    pub fn run_in_process(
+        &self,
+        script: &std::path::Path,
+        limits: &Limits,
+        host: &mut dyn Host,
+    ) -> std::io::Result<i32> {
+        let script = CString::new(script.to_string_lossy().into_owned())?;
+        let mut cx = Context {
+            host,
+            line: Vec::new(),
+        };
+        let raw = RawHost {
+            ctx: (&raw mut cx).cast(),
+            read,
+            write,
+        };
+        let run = match self {
+            #[cfg(feature = "lua51")]
+            crate::Lua::Lua51 => lua51_lunest_run,
+            #[cfg(feature = "lua52")]
+            crate::Lua::Lua52 => lua52_lunest_run,
+            #[cfg(feature = "lua53")]
+            crate::Lua::Lua53 => lua53_lunest_run,
+            #[cfg(feature = "lua54")]
+            crate::Lua::Lua54 => lua54_lunest_run,
+            #[cfg(feature = "luajit")]
+            crate::Lua::LuaJIT => luajit_lunest_run,
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("{} can't run in process", self.recommended_program_name()),
+                ));
+            }
+        };
+        Ok(unsafe { run(script.as_ptr(), limits, &raw) })
+    }
+}
+
+#[cfg(test)]
+mod run_in_process_tests {
+    use super::*;
+
+    /// Sends `inputs` and then `None`s, and keeps the written lines.
+    struct Lines {
+        inputs: std::collections::VecDeque<Vec<u8>>,
+        outputs: Vec<String>,
+    }
+
+    impl Host for Lines {
+        fn read(&mut self) -> Option<Vec<u8>> {
+            self.inputs.pop_front()
+        }
+
+        fn write(&mut self, line: &[u8]) {
+            self.outputs
+                .push(String::from_utf8_lossy(line).into_owned());
+        }
+    }
+
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(crate::Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(crate::Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(crate::Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(crate::Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(crate::Lua::LuaJIT))]
+    fn run(#[case] lua: crate::Lua) -> std::io::Result<()> {
+        let t = tempfile::tempdir()?;
+        let script = t.path().join("a.lua");
+        std::fs::write(
+            &script,
+            r#"
+                local host = LUNEST_HOST
+                while true do
+                    local line = host.read()
+                    if line == nil then break end
+                    host.write(line:upper())
+                end
+                error("done")
+            "#,
+        )?;
+        let mut host = Lines {
+            inputs: [b"a\n".to_vec(), b"b\n".to_vec()].into(),
+            outputs: Vec::new(),
+        };
+        assert_eq!(
+            1,
+            lua.run_in_process(&script, &Limits::default(), &mut host)?
+        );
+        assert_eq!(vec!["A\n", "B\n"], host.outputs);
+        Ok(())
+    }
+
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(crate::Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(crate::Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(crate::Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(crate::Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(crate::Lua::LuaJIT))]
+    fn exit(#[case] lua: crate::Lua) -> std::io::Result<()> {
+        let t = tempfile::tempdir()?;
+        let script = t.path().join("a.lua");
+        std::fs::write(
+            &script,
+            r#"
+                local ok, err = pcall(os.exit, 0)
+                LUNEST_HOST.write(tostring(ok) .. " " .. err)
+            "#,
+        )?;
+        let mut host = Lines {
+            inputs: Default::default(),
+            outputs: Vec::new(),
+        };
+        assert_eq!(
+            0,
+            lua.run_in_process(&script, &Limits::default(), &mut host)?
+        );
+        assert_eq!(
+            vec!["false os.exit is not supported in process"],
+            host.outputs
+        );
+        Ok(())
+    }
+
+    #[cfg(all(feature = "checked", feature = "lua54"))]
+    #[test]
+    fn checked() {
+        let mut host = Lines {
+            inputs: Default::default(),
+            outputs: Vec::new(),
+        };
+        let err = crate::Lua::Lua54Checked
+            .run_in_process(std::path::Path::new("a.lua"), &Limits::default(), &mut host)
+            .unwrap_err();
+        assert_eq!(std::io::ErrorKind::Unsupported, err.kind());
+        assert_eq!(
+            format!(
+                "{} can't run in process",
+                crate::Lua::Lua54Checked.recommended_program_name()
+            ),
+            err.to_string(),
+        );
+    }
+}
@@ -1,7 +1,14 @@
+mod hash;
+#[cfg(feature = "in-process")]
+mod in_process;
+
+#[cfg(feature = "in-process")]
+pub use in_process::Host;
+
 use std::{
     env::consts::{EXE_EXTENSION, EXE_SUFFIX},
     ffi::OsStr,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 fn decompress(data: &[u8], capacity: usize) -> Vec<u8> {
@@ -20,28 +27,192 @@ macro_rules! lazy_decompress {
         });
     };
 }
+#[cfg(feature = "lua54")]
 lazy_decompress!(LUA54, "lua54");
+#[cfg(feature = "lua53")]
 lazy_decompress!(LUA53, "lua53");
+#[cfg(feature = "lua52")]
 lazy_decompress!(LUA52, "lua52");
+#[cfg(feature = "lua51")]
 lazy_decompress!(LUA51, "lua51");
+#[cfg(feature = "luajit")]
+lazy_decompress!(LUAJIT, "luajit");
+#[cfg(all(feature = "checked", feature = "lua54"))]
+lazy_decompress!(LUA54_CHECKED, "lua54-checked");
+#[cfg(all(feature = "checked", feature = "lua53"))]
+lazy_decompress!(LUA53_CHECKED, "lua53-checked");
+#[cfg(all(feature = "checked", feature = "lua52"))]
+lazy_decompress!(LUA52_CHECKED, "lua52-checked");
+#[cfg(all(feature = "checked", feature = "lua51"))]
+lazy_decompress!(LUA51_CHECKED, "lua51-checked");
+#[cfg(all(feature = "checked", feature = "luajit"))]
+lazy_decompress!(LUAJIT_CHECKED, "luajit-checked");
+
+#[cfg(not(any(
+    feature = "lua51",
+    feature = "lua52",
+    feature = "lua53",
+    feature = "lua54",
+    feature = "luajit",
+)))]
+compile_error!("at least one Lua version must be enabled");
+
+macro_rules! content_hash {
+    ($version:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $version, "_hash.rs"))
+    };
+}
 
+/// Resource limits of the embedded interpreters, where zero means no limit.
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct Limits {
+    /// Maximum bytes allocated by Lua
+    pub memory: u64,
+    /// Maximum number of instructions executed between calls of `LUNEST_LIMITS.reset()`
+    pub instructions: u64,
+}
+
+impl Limits {
+    /// Returns the environment variables to pass the limits to the interpreter.
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        let mut envs = Vec::new();
+        if self.memory != 0 {
+            envs.push(("LUNEST_MEMORY_LIMIT", self.memory.to_string()));
+        }
+        if self.instructions != 0 {
+            envs.push(("LUNEST_INSTRUCTION_LIMIT", self.instructions.to_string()));
+        }
+        envs
+    }
+}
+
+/// Interpreters enabled by the cargo features of the same names.
+///
+/// The checked ones are built with API checks and assertions of Lua. The standalone interpreters
+/// collect all garbage every 100 instructions if `LUNEST_GC_STRESS` is set.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Lua {
+    #[cfg(feature = "lua51")]
     Lua51,
+    #[cfg(feature = "lua52")]
     Lua52,
+    #[cfg(feature = "lua53")]
     Lua53,
-    #[default]
+    #[cfg(feature = "lua54")]
     Lua54,
+    #[cfg(feature = "luajit")]
+    LuaJIT,
+    #[cfg(all(feature = "checked", feature = "lua51"))]
+    Lua51Checked,
+    #[cfg(all(feature = "checked", feature = "lua52"))]
+    Lua52Checked,
+    #[cfg(all(feature = "checked", feature = "lua53"))]
+    Lua53Checked,
+    #[cfg(all(feature = "checked", feature = "lua54"))]
+    Lua54Checked,
+    #[cfg(all(feature = "checked", feature = "luajit"))]
+    LuaJITChecked,
+}
+
+impl Default for Lua {
+    /// Returns the latest version enabled.
+    #[allow(unused_variables)]
+    fn default() -> Self {
+        // later ones take precedence
+        #[cfg(feature = "luajit")]
+        let lua = Lua::LuaJIT;
+        #[cfg(feature = "lua51")]
+        let lua = Lua::Lua51;
+        #[cfg(feature = "lua52")]
+        let lua = Lua::Lua52;
+        #[cfg(feature = "lua53")]
+        let lua = Lua::Lua53;
+        #[cfg(feature = "lua54")]
+        let lua = Lua::Lua54;
+        lua
+    }
 }
 
 impl Lua {
     pub fn get_bytes(&self) -> &'static [u8] {
         match self {
+            #[cfg(feature = "lua54")]
             Lua::Lua54 => LUA54.as_slice(),
+            #[cfg(feature = "lua53")]
             Lua::Lua53 => LUA53.as_slice(),
+            #[cfg(feature = "lua52")]
             Lua::Lua52 => LUA52.as_slice(),
+            #[cfg(feature = "lua51")]
             Lua::Lua51 => LUA51.as_slice(),
+            #[cfg(feature = "luajit")]
+            Lua::LuaJIT => LUAJIT.as_slice(),
+            #[cfg(all(feature = "checked", feature = "lua51"))]
+            Lua::Lua51Checked => LUA51_CHECKED.as_slice(),
+            #[cfg(all(feature = "checked", feature = "lua52"))]
+            Lua::Lua52Checked => LUA52_CHECKED.as_slice(),
+            #[cfg(all(feature = "checked", feature = "lua53"))]
+            Lua::Lua53Checked => LUA53_CHECKED.as_slice(),
+            #[cfg(all(feature = "checked", feature = "lua54"))]
+            Lua::Lua54Checked => LUA54_CHECKED.as_slice(),
+            #[cfg(all(feature = "checked", feature = "luajit"))]
+            Lua::LuaJITChecked => LUAJIT_CHECKED.as_slice(),
+        }
+    }
+
+    /// Returns the hash of [`Lua::get_bytes`] without decompressing them.
+    pub fn content_hash(&self) -> u64 {
+        match self {
+            #[cfg(feature = "lua54")]
+            Lua::Lua54 => content_hash!("lua54"),
+            #[cfg(feature = "lua53")]
+            Lua::Lua53 => content_hash!("lua53"),
+            #[cfg(feature = "lua52")]
+            Lua::Lua52 => content_hash!("lua52"),
+            #[cfg(feature = "lua51")]
+            Lua::Lua51 => content_hash!("lua51"),
+            #[cfg(feature = "luajit")]
+            Lua::LuaJIT => content_hash!("luajit"),
+            #[cfg(all(feature = "checked", feature = "lua51"))]
+            Lua::Lua51Checked => content_hash!("lua51-checked"),
+            #[cfg(all(feature = "checked", feature = "lua52"))]
+            Lua::Lua52Checked => content_hash!("lua52-checked"),
+            #[cfg(all(feature = "checked", feature = "lua53"))]
+            Lua::Lua53Checked => content_hash!("lua53-checked"),
+            #[cfg(all(feature = "checked", feature = "lua54"))]
+            Lua::Lua54Checked => content_hash!("lua54-checked"),
+            #[cfg(all(feature = "checked", feature = "luajit"))]
+            Lua::LuaJITChecked => content_hash!("luajit-checked"),
+        }
+    }
+
+    /// Writes the interpreter into a subdirectory of `dir` named after [`Lua::content_hash`],
+    /// unless an intact one already exists there, and returns the path to it.
+    pub fn install(&self, dir: impl AsRef<Path>) -> std::io::Result<PathBuf> {
+        let dir = dir.as_ref().join(format!("{:016x}", self.content_hash()));
+        let path = dir.join(self.recommended_program_name());
+        if self.is_installed(&path) {
+            return Ok(path);
+        }
+        std::fs::create_dir_all(&dir)?;
+        let temp_path = dir.join(format!(
+            "{}.{}.tmp",
+            self.recommended_program_name(),
+            std::process::id()
+        ));
+        self.write(&temp_path)?;
+        if let Err(e) = std::fs::rename(&temp_path, &path) {
+            _ = std::fs::remove_file(&temp_path);
+            // another process may have installed it at the same time
+            if !self.is_installed(&path) {
+                return Err(e);
+            }
         }
+        Ok(path)
+    }
+
+    fn is_installed(&self, path: &Path) -> bool {
+        std::fs::read(path).is_ok_and(|b| hash::fnv1a(&b) == self.content_hash())
     }
 
     pub fn write(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
@@ -54,50 +225,127 @@ impl Lua {
         Ok(())
     }
 
+    /// Returns the interpreter for `program`, or `None` if it is unknown or disabled.
     pub fn from_program_name(program: impl AsRef<OsStr>) -> Option<Self> {
-        let program = Path::new(program.as_ref());
-        let file_name = if program.extension() == Some(OsStr::new(EXE_EXTENSION)) {
-            program.file_stem()?
-        } else {
-            program.file_name()?
-        };
-        match file_name.to_str()? {
+        match program_stem(program.as_ref())? {
             "lua" => Some(Lua::default()),
+            #[cfg(feature = "lua51")]
             "lua5.1" => Some(Lua::Lua51),
+            #[cfg(feature = "lua52")]
             "lua5.2" => Some(Lua::Lua52),
+            #[cfg(feature = "lua53")]
             "lua5.3" => Some(Lua::Lua53),
+            #[cfg(feature = "lua54")]
             "lua5.4" => Some(Lua::Lua54),
+            #[cfg(feature = "luajit")]
+            "luajit" => Some(Lua::LuaJIT),
+            #[cfg(all(feature = "checked", feature = "lua51"))]
+            "lua5.1-checked" => Some(Lua::Lua51Checked),
+            #[cfg(all(feature = "checked", feature = "lua52"))]
+            "lua5.2-checked" => Some(Lua::Lua52Checked),
+            #[cfg(all(feature = "checked", feature = "lua53"))]
+            "lua5.3-checked" => Some(Lua::Lua53Checked),
+            #[cfg(all(feature = "checked", feature = "lua54"))]
+            "lua5.4-checked" => Some(Lua::Lua54Checked),
+            #[cfg(all(feature = "checked", feature = "luajit"))]
+            "luajit-checked" => Some(Lua::LuaJITChecked),
             _ => None,
         }
     }
 
+    /// Returns the cargo features that embed the interpreter for `program`, whether they are
+    /// enabled or not.
+    pub fn features_for_program_name(
+        program: impl AsRef<OsStr>,
+    ) -> Option<&'static [&'static str]> {
+        match program_stem(program.as_ref())? {
+            "lua5.1" => Some(&["lua51"]),
+            "lua5.2" => Some(&["lua52"]),
+            "lua5.3" => Some(&["lua53"]),
+            "lua5.4" => Some(&["lua54"]),
+            "luajit" => Some(&["luajit"]),
+            "lua5.1-checked" => Some(&["lua51", "checked"]),
+            "lua5.2-checked" => Some(&["lua52", "checked"]),
+            "lua5.3-checked" => Some(&["lua53", "checked"]),
+            "lua5.4-checked" => Some(&["lua54", "checked"]),
+            "luajit-checked" => Some(&["luajit", "checked"]),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the interpreter is built with API checks and assertions.
+    pub fn is_checked(&self) -> bool {
+        self.recommended_program_name().contains("-checked")
+    }
+
     pub fn recommended_program_name(&self) -> String {
-        let mut s = String::with_capacity(10);
+        let mut s = String::with_capacity(18);
         s.push_str(match self {
+            #[cfg(feature = "lua51")]
             Lua::Lua51 => "lua5.1",
+            #[cfg(feature = "lua52")]
             Lua::Lua52 => "lua5.2",
+            #[cfg(feature = "lua53")]
             Lua::Lua53 => "lua5.3",
+            #[cfg(feature = "lua54")]
             Lua::Lua54 => "lua5.4",
+            #[cfg(feature = "luajit")]
+            Lua::LuaJIT => "luajit",
+            #[cfg(all(feature = "checked", feature = "lua51"))]
+            Lua::Lua51Checked => "lua5.1-checked",
+            #[cfg(all(feature = "checked", feature = "lua52"))]
+            Lua::Lua52Checked => "lua5.2-checked",
+            #[cfg(all(feature = "checked", feature = "lua53"))]
+            Lua::Lua53Checked => "lua5.3-checked",
+            #[cfg(all(feature = "checked", feature = "lua54"))]
+            Lua::Lua54Checked => "lua5.4-checked",
+            #[cfg(all(feature = "checked", feature = "luajit"))]
+            Lua::LuaJITChecked => "luajit-checked",
         });
         s.push_str(EXE_SUFFIX);
         s
     }
 }
 
+fn program_stem(program: &OsStr) -> Option<&str> {
+    let program = Path::new(program);
+    let file_name = if program.extension() == Some(OsStr::new(EXE_EXTENSION)) {
+        program.file_stem()?
+    } else {
+        program.file_name()?
+    };
+    file_name.to_str()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[rstest::rstest]
     #[case(Some(Lua::default()), "lua")]
-    #[case(Some(Lua::Lua54), "lua5.4")]
+    #[cfg_attr(feature = "lua54", case(Some(Lua::Lua54), "lua5.4"))]
+    #[cfg_attr(feature = "luajit", case(Some(Lua::LuaJIT), "luajit"))]
+    #[cfg_attr(
+        all(feature = "checked", feature = "lua54"),
+        case(Some(Lua::Lua54Checked), "lua5.4-checked")
+    )]
     #[case(None, "lua5.0")]
     #[cfg_attr(unix, case(None, "lua5.1.exe"))]
-    #[cfg_attr(windows, case(Some(Lua::Lua51), "lua5.1.exe"))]
+    #[cfg_attr(all(windows, feature = "lua51"), case(Some(Lua::Lua51), "lua5.1.exe"))]
     fn from_program_name(#[case] lua: Option<Lua>, #[case] program: &str) {
         assert_eq!(lua, Lua::from_program_name(program));
     }
 
+    #[rstest::rstest]
+    #[case(Some(&["lua52"][..]), "lua5.2")]
+    #[case(Some(&["luajit"][..]), "/usr/bin/luajit")]
+    #[case(Some(&["lua54", "checked"][..]), "lua5.4-checked")]
+    #[case(None, "lua")]
+    fn features_for_program_name(#[case] features: Option<&[&str]>, #[case] program: &str) {
+        assert_eq!(features, Lua::features_for_program_name(program));
+    }
+
+    #[cfg(feature = "lua51")]
     #[test]
     fn recommended_program_name() {
         #[cfg(unix)]
@@ -106,11 +354,169 @@ mod tests {
         assert_eq!(format!("lua5.1.exe"), Lua::Lua51.recommended_program_name());
     }
 
+    #[test]
+    fn install() -> std::io::Result<()> {
+        let t = tempfile::tempdir()?;
+        let lua = Lua::default();
+        let p = lua.install(t.path())?;
+        assert_eq!(lua.get_bytes(), std::fs::read(&p)?);
+        assert_eq!(p, lua.install(t.path())?);
+
+        std::fs::write(&p, b"broken")?;
+        assert_eq!(p, lua.install(t.path())?);
+        assert_eq!(lua.get_bytes(), std::fs::read(&p)?);
+        Ok(())
+    }
+
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJIT))]
+    fn limits(#[case] lua: Lua) -> std::io::Result<()> {
+        let (_t, mut cmd) = script_command(
+            lua,
+            r#"
+                print(pcall(function() local t = {} while true do t[#t + 1] = {} end end))
+                print(pcall(function() while true do end end))
+            "#,
+        )?;
+        cmd.envs(
+            Limits {
+                memory: 1 << 20,
+                instructions: 1 << 16,
+            }
+            .envs(),
+        );
+        let stdout = success_stdout(&mut cmd)?;
+        let mut lines = stdout.lines();
+        assert!(lines.next().unwrap().contains("not enough memory"));
+        assert!(lines.next().unwrap().contains("instruction limit exceeded"));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJIT))]
+    fn c_module(#[case] lua: Lua) -> std::io::Result<()> {
+        let (t, mut cmd) = script_command(
+            lua,
+            "package.cpath = arg[1] .. '/?.so'\nprint(require('hello'))\n",
+        )?;
+        // declared by hand to build without the headers
+        std::fs::write(
+            t.path().join("hello.c"),
+            r#"
+                void lua_pushstring(void *L, const char *s);
+                int luaopen_hello(void *L) {
+                    lua_pushstring(L, "hello");
+                    return 1;
+                }
+            "#,
+        )?;
+        let status = std::process::Command::new(std::env::var_os("CC").unwrap_or("cc".into()))
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(t.path().join("hello.so"))
+            .arg(t.path().join("hello.c"))
+            .status()?;
+        assert!(status.success());
+
+        assert_eq!("hello\n", success_stdout(cmd.arg(t.path()))?);
+        Ok(())
+    }
+
+    #[cfg(feature = "lpeg")]
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJIT))]
+    fn lpeg(#[case] lua: Lua) -> std::io::Result<()> {
+        let (_t, mut cmd) = script_command(
+            lua,
+            "local lpeg = require('lpeg')\nprint(lpeg.match(lpeg.C(lpeg.P('a') ^ 1), 'aab'))\n",
+        )?;
+        assert_eq!("aa\n", success_stdout(&mut cmd)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "lfs")]
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJIT))]
+    fn lfs(#[case] lua: Lua) -> std::io::Result<()> {
+        let (_t, mut cmd) =
+            script_command(lua, "print(require('lfs').attributes(arg[0], 'mode'))\n")?;
+        assert_eq!("file\n", success_stdout(&mut cmd)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "checked")]
+    #[rstest::rstest]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51Checked))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52Checked))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53Checked))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54Checked))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJITChecked))]
+    fn gc_stress(#[case] lua: Lua) -> std::io::Result<()> {
+        let (_t, mut cmd) = script_command(
+            lua,
+            "local t = {}\nfor i = 1, 1000 do t[i] = { i } end\nprint(#t)\n",
+        )?;
+        cmd.env("LUNEST_GC_STRESS", "1");
+        assert_eq!("1000\n", success_stdout(&mut cmd)?);
+        Ok(())
+    }
+
+    /// Writes the interpreter and `script` into a temporary directory, and returns the directory
+    /// with the command running the script.
+    fn script_command(
+        lua: Lua,
+        script: &str,
+    ) -> std::io::Result<(tempfile::TempDir, std::process::Command)> {
+        let t = tempfile::tempdir()?;
+        let p = t.path().join(lua.recommended_program_name());
+        lua.write(&p)?;
+        std::fs::write(t.path().join("a.lua"), script)?;
+        let mut cmd = std::process::Command::new(&p);
+        cmd.arg(t.path().join("a.lua"));
+        Ok((t, cmd))
+    }
+
+    /// Runs `cmd`, which must succeed, and returns the stdout.
+    fn success_stdout(cmd: &mut std::process::Command) -> std::io::Result<String> {
+        let out = cmd.output()?;
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        Ok(String::from_utf8_lossy(&out.stdout).replace("\r\n", "\n"))
+    }
+
     #[rstest::rstest]
-    #[case(Lua::Lua51, "Lua 5.1")]
-    #[case(Lua::Lua52, "Lua 5.2")]
-    #[case(Lua::Lua53, "Lua 5.3")]
-    #[case(Lua::Lua54, "Lua 5.4")]
+    #[cfg_attr(feature = "lua51", case(Lua::Lua51, "Lua 5.1"))]
+    #[cfg_attr(feature = "lua52", case(Lua::Lua52, "Lua 5.2"))]
+    #[cfg_attr(feature = "lua53", case(Lua::Lua53, "Lua 5.3"))]
+    #[cfg_attr(feature = "lua54", case(Lua::Lua54, "Lua 5.4"))]
+    #[cfg_attr(feature = "luajit", case(Lua::LuaJIT, "Lua 5.1"))]
+    #[cfg_attr(
+        all(feature = "checked", feature = "lua51"),
+        case(Lua::Lua51Checked, "Lua 5.1")
+    )]
+    #[cfg_attr(
+        all(feature = "checked", feature = "lua54"),
+        case(Lua::Lua54Checked, "Lua 5.4")
+    )]
     fn write(#[case] lua: Lua, #[case] version: &str) -> std::io::Result<()> {
         let t = tempfile::tempdir()?;
         let p = t.path().join(lua.recommended_program_name());
@@ -0,0 +1,88 @@
+//! Entry point of the in-process backend.
+//! The global symbols of this library are prefixed by `lua-rt/build.rs`.
+
+const std = @import("std");
+
+const ziglua = @import("ziglua");
+const Lua = ziglua.Lua;
+
+const bundled = @import("bundled.zig");
+const limit = @import("limit.zig");
+const main = @import("main.zig");
+
+/// Callbacks to exchange lines with the host.
+const Host = extern struct {
+    ctx: ?*anyopaque,
+    /// Returns the next line, or null if there is no input for now.
+    read: *const fn (ctx: ?*anyopaque, len: *usize) callconv(.c) ?[*]const u8,
+    write: *const fn (ctx: ?*anyopaque, ptr: [*]const u8, len: usize) callconv(.c) void,
+};
+
+/// Runs the script with the `LUNEST_HOST` global and returns the exit status.
+export fn lunest_run(script: [*:0]const u8, limits: *const limit.Limits, host: *const Host) c_int {
+    run(std.mem.span(script), limits.*, host) catch |e| switch (e) {
+        error.LuaRuntime => return 1,
+        else => {
+            std.debug.print("failed to run {s}: {s}\n", .{ script, @errorName(e) });
+            return 1;
+        },
+    };
+    return 0;
+}
+
+fn run(script: [:0]const u8, limits: limit.Limits, host: *const Host) !void {
+    var limited = limit.LimitedAllocator{ .child = std.heap.c_allocator, .limit = limits.memory };
+    var lua = try Lua.init(limited.allocator());
+    defer lua.deinit();
+    lua.openLibs();
+    // exiting would end the host process too
+    _ = try lua.getGlobal("os");
+    lua.pushFunction(ziglua.wrap(exit));
+    lua.setField(-2, "exit");
+    lua.pop(1);
+    try bundled.preload(lua);
+    limit.install(lua, limits);
+
+    lua.createTable(0, 1);
+    _ = lua.pushString(script);
+    lua.rawSetIndex(-2, 0);
+    lua.setGlobal("arg");
+
+    lua.createTable(0, 2);
+    lua.pushLightUserdata(@constCast(host));
+    lua.pushClosure(ziglua.wrap(read), 1);
+    lua.setField(-2, "read");
+    lua.pushLightUserdata(@constCast(host));
+    lua.pushClosure(ziglua.wrap(write), 1);
+    lua.setField(-2, "write");
+    lua.setGlobal("LUNEST_HOST");
+
+    lua.pushFunction(ziglua.wrap(main.traceback));
+    switch (ziglua.lang) {
+        .luajit, .lua51 => try lua.loadFile(script),
+        else => try lua.loadFile(script, .binary_text),
+    }
+    try lua.protectedCall(.{
+        .results = ziglua.mult_return,
+        .msg_handler = -2,
+    });
+}
+
+fn exit(lua: *Lua) i32 {
+    lua.raiseErrorStr("os.exit is not supported in process", .{});
+}
+
+fn read(lua: *Lua) !i32 {
+    const host = try lua.toUserdata(Host, Lua.upvalueIndex(1));
+    var len: usize = 0;
+    const ptr = host.read(host.ctx, &len) orelse return 0;
+    _ = lua.pushString(ptr[0..len]);
+    return 1;
+}
+
+fn write(lua: *Lua) !i32 {
+    const host = try lua.toUserdata(Host, Lua.upvalueIndex(1));
+    const s = try lua.toString(1);
+    host.write(host.ctx, s.ptr, s.len);
+    return 0;
+}
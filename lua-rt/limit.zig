@@ -0,0 +1,145 @@
+//! Resource limits of the embedded interpreters.
+
+const std = @import("std");
+
+const ziglua = @import("ziglua");
+const Lua = ziglua.Lua;
+
+/// Zero means no limit. Keep in sync with `Limits` in `lua-rt/lib.rs`.
+pub const Limits = extern struct {
+    /// Maximum bytes allocated by Lua
+    memory: u64 = 0,
+    /// Maximum number of instructions executed between resets
+    instructions: u64 = 0,
+
+    /// Reads `LUNEST_MEMORY_LIMIT` and `LUNEST_INSTRUCTION_LIMIT`.
+    pub fn fromEnv(a: std.mem.Allocator) !Limits {
+        return .{
+            .memory = try parseEnv(a, "LUNEST_MEMORY_LIMIT"),
+            .instructions = try parseEnv(a, "LUNEST_INSTRUCTION_LIMIT"),
+        };
+    }
+};
+
+fn parseEnv(a: std.mem.Allocator, key: []const u8) !u64 {
+    const value = std.process.getEnvVarOwned(a, key) catch |e| switch (e) {
+        error.EnvironmentVariableNotFound => return 0,
+        else => return e,
+    };
+    defer a.free(value);
+    return std.fmt.parseInt(u64, value, 10) catch {
+        std.debug.print("invalid value of {s}: {s}\n", .{ key, value });
+        return error.InvalidLimit;
+    };
+}
+
+/// Fails allocations once the total size exceeds the limit, which Lua reports as
+/// "not enough memory" error.
+pub const LimitedAllocator = struct {
+    child: std.mem.Allocator,
+    limit: u64,
+    used: u64 = 0,
+
+    pub fn allocator(self: *LimitedAllocator) std.mem.Allocator {
+        return .{
+            .ptr = self,
+            .vtable = &.{
+                .alloc = alloc,
+                .resize = resize,
+                .remap = remap,
+                .free = free,
+            },
+        };
+    }
+
+    fn reserve(self: *LimitedAllocator, old_len: usize, new_len: usize) bool {
+        if (self.limit == 0 or new_len <= old_len) return true;
+        return self.used - old_len + new_len <= self.limit;
+    }
+
+    fn alloc(ctx: *anyopaque, len: usize, alignment: std.mem.Alignment, ret_addr: usize) ?[*]u8 {
+        const self: *LimitedAllocator = @ptrCast(@alignCast(ctx));
+        if (!self.reserve(0, len)) return null;
+        const ptr = self.child.rawAlloc(len, alignment, ret_addr) orelse return null;
+        self.used += len;
+        return ptr;
+    }
+
+    fn resize(ctx: *anyopaque, memory: []u8, alignment: std.mem.Alignment, new_len: usize, ret_addr: usize) bool {
+        const self: *LimitedAllocator = @ptrCast(@alignCast(ctx));
+        if (!self.reserve(memory.len, new_len)) return false;
+        if (!self.child.rawResize(memory, alignment, new_len, ret_addr)) return false;
+        self.used = self.used - memory.len + new_len;
+        return true;
+    }
+
+    fn remap(ctx: *anyopaque, memory: []u8, alignment: std.mem.Alignment, new_len: usize, ret_addr: usize) ?[*]u8 {
+        const self: *LimitedAllocator = @ptrCast(@alignCast(ctx));
+        if (!self.reserve(memory.len, new_len)) return null;
+        const ptr = self.child.rawRemap(memory, alignment, new_len, ret_addr) orelse return null;
+        self.used = self.used - memory.len + new_len;
+        return ptr;
+    }
+
+    fn free(ctx: *anyopaque, memory: []u8, alignment: std.mem.Alignment, ret_addr: usize) void {
+        const self: *LimitedAllocator = @ptrCast(@alignCast(ctx));
+        self.child.rawFree(memory, alignment, ret_addr);
+        self.used -= memory.len;
+    }
+};
+
+const hook_interval = 1000;
+/// Instructions between the full collections of `setGcStress`
+const gc_stress_interval = 100;
+
+// thread-local since the in-process backend runs the states on several threads
+threadlocal var instruction_limit: u64 = 0;
+threadlocal var instruction_count: u64 = 0;
+threadlocal var gc_stress = false;
+
+/// Sets the instruction budget and the `LUNEST_LIMITS` global, whose `reset()` restarts counting.
+pub fn install(lua: *Lua, limits: Limits) void {
+    instruction_limit = limits.instructions;
+    instruction_count = 0;
+    gc_stress = false;
+    setHook(lua);
+
+    lua.createTable(0, 1);
+    lua.pushFunction(ziglua.wrap(reset));
+    lua.setField(-2, "reset");
+    lua.setGlobal("LUNEST_LIMITS");
+}
+
+/// Makes the hook of `install` collect all garbage every `gc_stress_interval` instructions.
+pub fn setGcStress(lua: *Lua) void {
+    gc_stress = true;
+    setHook(lua);
+}
+
+// both share the count hook, since a state has only one
+fn setHook(lua: *Lua) void {
+    if (gc_stress) {
+        lua.setHook(ziglua.wrap(hook), .{ .count = true }, gc_stress_interval);
+    } else if (instruction_limit != 0) {
+        lua.setHook(ziglua.wrap(hook), .{ .count = true }, hook_interval);
+    }
+}
+
+fn hook(lua: *Lua, _: ziglua.Event, _: *ziglua.DebugInfo) void {
+    if (gc_stress) lua.gcCollect();
+    if (instruction_limit == 0) return;
+    instruction_count += if (gc_stress) gc_stress_interval else hook_interval;
+    if (instruction_count > instruction_limit) {
+        // let the caller handle the error without hitting the limit again
+        instruction_count = 0;
+        // formatted here since `%d` of `lua_pushfstring` takes an `int` before Lua 5.3
+        var buf: [std.fmt.count("{d}", .{std.math.maxInt(u64)}) + 1]u8 = undefined;
+        const limit = std.fmt.bufPrintZ(&buf, "{d}", .{instruction_limit}) catch unreachable;
+        lua.raiseErrorStr("instruction limit exceeded (%s)", .{limit.ptr});
+    }
+}
+
+fn reset(_: *Lua) i32 {
+    instruction_count = 0;
+    return 0;
+}
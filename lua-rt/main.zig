@@ -4,12 +4,22 @@ const std = @import("std");
 const ziglua = @import("ziglua");
 const Lua = ziglua.Lua;
 
+const bundled = @import("bundled.zig");
+const limit = @import("limit.zig");
+
 pub fn main() !void {
     const a = std.heap.c_allocator;
 
-    var lua = try Lua.init(a);
+    const limits = try limit.Limits.fromEnv(a);
+    var limited = limit.LimitedAllocator{ .child = a, .limit = limits.memory };
+    var lua = try Lua.init(limited.allocator());
     defer lua.deinit();
     lua.openLibs();
+    try bundled.preload(lua);
+    limit.install(lua, limits);
+    if (try std.process.hasEnvVar(a, "LUNEST_GC_STRESS")) {
+        limit.setGcStress(lua);
+    }
 
     var args = if (builtin.os.tag == .windows)
         try std.process.argsWithAllocator(a)
@@ -32,6 +42,8 @@ pub fn main() !void {
         lua.setGlobal("arg");
     }
 
+    if (file_name == null) return repl(lua);
+
     lua.pushFunction(ziglua.wrap(traceback));
     switch (ziglua.lang) {
         .luajit, .lua51 => try lua.loadFile(file_name.?),
@@ -46,7 +58,71 @@ pub fn main() !void {
     };
 }
 
-fn traceback(lua: *Lua) !i32 {
+/// Reads statements or expressions from stdin and prints their results.
+fn repl(lua: *Lua) !void {
+    const stdin = std.io.getStdIn().reader();
+    const stderr = std.io.getStdErr().writer();
+    var line = std.ArrayList(u8).init(lua.allocator());
+    defer line.deinit();
+    var chunk = std.ArrayList(u8).init(lua.allocator());
+    defer chunk.deinit();
+
+    while (true) {
+        try stderr.writeAll(if (chunk.items.len == 0) "> " else ">> ");
+        line.clearRetainingCapacity();
+        stdin.streamUntilDelimiter(line.writer(), '\n', null) catch |e| switch (e) {
+            error.EndOfStream => if (line.items.len == 0) {
+                try stderr.writeAll("\n");
+                return;
+            },
+            else => return e,
+        };
+        if (chunk.items.len != 0) try chunk.append('\n');
+        try chunk.appendSlice(line.items);
+
+        const top = lua.getTop();
+        // try it as an expression first, like the standalone interpreter
+        const expr = try std.fmt.allocPrintZ(lua.allocator(), "return {s}", .{chunk.items});
+        defer lua.allocator().free(expr);
+        lua.loadString(expr) catch {
+            lua.pop(1);
+            const stat = try lua.allocator().dupeZ(u8, chunk.items);
+            defer lua.allocator().free(stat);
+            lua.loadString(stat) catch |e| {
+                const msg = lua.toString(-1) catch "(error object is not a string)";
+                lua.pop(1);
+                // wait for more lines if the statement is incomplete; the message ends with
+                // `near <eof>` since Lua 5.2, and with `near '<eof>'` in Lua 5.1 and LuaJIT
+                const incomplete = std.mem.endsWith(u8, msg, "<eof>") or
+                    std.mem.endsWith(u8, msg, "'<eof>'");
+                if (e == error.LuaSyntax and incomplete) continue;
+                try stderr.print("{s}\n", .{msg});
+                chunk.clearRetainingCapacity();
+                continue;
+            };
+        };
+        chunk.clearRetainingCapacity();
+
+        lua.protectedCall(.{ .results = ziglua.mult_return }) catch {
+            const msg = lua.toString(-1) catch "(error object is not a string)";
+            try stderr.print("{s}\n", .{msg});
+            lua.setTop(top);
+            continue;
+        };
+        const n = lua.getTop() - top;
+        if (n > 0) {
+            _ = try lua.getGlobal("print");
+            lua.insert(top + 1);
+            lua.protectedCall(.{ .args = n }) catch {
+                const msg = lua.toString(-1) catch "(error object is not a string)";
+                try stderr.print("{s}\n", .{msg});
+            };
+        }
+        lua.setTop(top);
+    }
+}
+
+pub fn traceback(lua: *Lua) !i32 {
     var args = if (builtin.os.tag == .windows)
         try std.process.argsWithAllocator(lua.allocator())
     else
@@ -54,6 +130,9 @@ fn traceback(lua: *Lua) !i32 {
     defer args.deinit();
     const exe = args.next().?;
     const errmsg = try lua.toString(-1);
+    writeCrashReport(lua, errmsg) catch |e| {
+        std.debug.print("{s}: failed to write the crash report: {}\n", .{ exe, e });
+    };
     (if (ziglua.lang == .lua51) tracebackLua51 else Lua.traceback)(lua, lua, errmsg, 1);
     const trace = try lua.toString(-1);
     std.debug.print("{s}: {s}\n", .{ exe, trace });
@@ -61,6 +140,46 @@ fn traceback(lua: *Lua) !i32 {
     return 0;
 }
 
+const Frame = struct {
+    source: []const u8,
+    line: ?i32,
+    name: ?[]const u8,
+};
+
+/// Writes the error and the call stack as JSON to the path in `LUNEST_CRASH_REPORT`, if it's set.
+fn writeCrashReport(lua: *Lua, message: []const u8) !void {
+    // the allocator of the state may be out of its memory limit
+    var arena = std.heap.ArenaAllocator.init(std.heap.c_allocator);
+    defer arena.deinit();
+    const a = arena.allocator();
+    const path = std.process.getEnvVarOwned(a, "LUNEST_CRASH_REPORT") catch |e| switch (e) {
+        error.EnvironmentVariableNotFound => return,
+        else => return e,
+    };
+
+    var frames = std.ArrayList(Frame).init(a);
+    var level: i32 = 1;
+    while (lua.getStack(level) catch null) |debuginfo| : (level += 1) {
+        var info = debuginfo;
+        lua.getInfo(.{ .S = true, .l = true, .n = true }, &info);
+        try frames.append(.{
+            .source = try a.dupe(u8, std.mem.sliceTo(&info.short_src, 0)),
+            .line = info.current_line,
+            .name = info.name,
+        });
+    }
+
+    const file = try std.fs.cwd().createFile(path, .{});
+    defer file.close();
+    var buffered = std.io.bufferedWriter(file.writer());
+    try std.json.stringify(.{
+        .message = message,
+        .traceback = frames.items,
+        .version = @tagName(ziglua.lang),
+    }, .{}, buffered.writer());
+    try buffered.flush();
+}
+
 fn tracebackLua51(lua: *Lua, state: *Lua, msg: ?[:0]const u8, level: i32) void {
     const top = lua.getTop();
     var lv = level;
@@ -1,15 +1,54 @@
 -- NOTE: **DO NOT** import this module directly from the other modules using `require()`.
 
+local Channel = require("lunest.Channel")
 local Context = require("lunest.Context")
 local Group = require("lunest.Group")
 local Process = require("lunest.Process")
 local Test = require("lunest.Test")
 local assertion = require("lunest.assertion")
+local module = require("lunest.module")
+
+-- The standard output is the one of the host when running in process, so send what the tests
+-- print as logs instead.
+---@param process lunest.Process
+local function capture_output(process)
+    _G.print = function(...)
+        local s = {}
+        for i = 1, select("#", ...) do
+            s[i] = tostring((select(i, ...)))
+        end
+        process:log("%s", table.concat(s, "\t"))
+    end
+    _G.io.write = function(...)
+        local s = {}
+        for i = 1, select("#", ...) do
+            local v = select(i, ...)
+            if type(v) ~= "string" and type(v) ~= "number" then
+                local msg = "bad argument #%d to 'write' (string expected, got %s)"
+                error(msg:format(i, type(v)), 2)
+            end
+            s[i] = v
+        end
+        process:log("%s", (table.concat(s):gsub("\n$", "")))
+        return _G.io.stdout
+    end
+end
 
 local function main()
-    local process = Process.open(assert(os.getenv("LUNEST_IN")), assert(os.getenv("LUNEST_OUT")))
+    local process
+    local host = _G.LUNEST_HOST
+    if host then
+        _G.LUNEST_HOST = nil
+        process = Process.new(Channel.new(host.read), Channel.new(nil, host.write))
+        capture_output(process)
+    else
+        process = Process.open(assert(os.getenv("LUNEST_IN")), assert(os.getenv("LUNEST_OUT")))
+    end
+    process:send_hello()
     process:log("start")
-    local cx = Context.new(process)
+    local limits = _G.LUNEST_LIMITS
+    _G.LUNEST_LIMITS = nil
+    local cx = Context.new(process, limits)
 
     do
         local M = {}
@@ -30,6 +69,12 @@ local function main()
         end
     end
 
+    process:on_initialize(function(input)
+        -- `package` of the bundle is a copy, whose paths are not used by `require` of the files
+        _G.package.path = module.prepend_path(_G.package.path, input.path)
+        _G.package.cpath = module.prepend_path(_G.package.cpath, input.cpath)
+    end)
+
     process:on_execute(function(script)
         dofile(script)
         process:log("executed %q", script)
@@ -44,6 +89,14 @@ local function main()
     process:loop()
 end
 
-if _G.arg[0] and ("@" .. _G.arg[0]) == debug.getinfo(1, "S").source then
+---@param level integer
+---@return boolean
+local function is_script(level)
+    local info = debug.getinfo(level + 1, "S")
+    return info ~= nil and _G.arg[0] ~= nil and info.source == "@" .. _G.arg[0]
+end
+
+-- when bundled, this chunk is called from the script
+if is_script(1) or is_script(2) then
     main()
 end
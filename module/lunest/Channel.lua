@@ -0,0 +1,60 @@
+--- Lines exchanged with the host through functions, used instead of `lunest.File` when lunest runs
+--- the script in its own process.
+---@class lunest.Channel
+---@field private _read (fun(): string?)?
+---@field private _write fun(line: string)?
+---@field private closed boolean
+local M = {}
+
+local test = require("lunest.wrapper")
+local assertion = test.assertion
+
+---@private
+M.__index = M
+
+---@param read (fun(): string?)?
+---@param write fun(line: string)?
+---@return self
+function M.new(read, write)
+    local self = setmetatable({}, M)
+    self._read = read
+    self._write = write
+    self.closed = false
+    return self
+end
+
+function M:close()
+    self.closed = true
+end
+
+---@return boolean
+function M:is_closed()
+    return self.closed
+end
+
+---@param ... string | number
+function M:writeln(...)
+    assert(self._write, "channel is not writable")(table.concat({ ... }) .. "\n")
+end
+
+---@return string?
+function M:readln()
+    return assert(self._read, "channel is not readable")()
+end
+
+test.test("readln and writeln", function()
+    local lines = {}
+    local input = M.new(function()
+        return table.remove(lines, 1)
+    end)
+    local output = M.new(nil, function(line)
+        table.insert(lines, line)
+    end)
+    output:writeln("hello", 1)
+    assertion.eq("hello1\n", input:readln())
+    assertion.eq(nil, input:readln())
+    input:close()
+    assert(input:is_closed())
+end)
+
+return M
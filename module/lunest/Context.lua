@@ -3,9 +3,11 @@
 ---@field package _process lunest.Process
 ---@field package _test_mode lunest.TestMode
 ---@field package _root_dir string
+---@field package _cwd string
 ---@field package _target_files { name: string, path: string }[]
 ---@field package _term_width integer
 ---@field package _send_info_only boolean
+---@field package _limits { reset: fun() }?
 local M = {}
 
 local id = require("lunest.id")
@@ -14,13 +16,16 @@ local id = require("lunest.id")
 M.__index = M
 
 ---@param process lunest.Process
+---@param limits { reset: fun() }? resource limits of the embedded interpreter
 ---@return self
-function M.new(process)
+function M.new(process, limits)
     local self = setmetatable({}, M)
     self._process = process
+    self._limits = limits
 
     process:on_initialize(function(input)
         self._root_dir = input.root_dir
+        self._cwd = input.cwd
         self._target_files = input.target_files
         self._term_width = input.term_width
     end)
@@ -59,6 +64,11 @@ function M:root_dir()
     return self._root_dir
 end
 
+---@return string
+function M:cwd()
+    return self._cwd
+end
+
 ---@return { name: string, path: string }[]
 function M:target_files()
     return self._target_files
@@ -69,4 +79,11 @@ function M:term_width()
     return self._term_width
 end
 
+--- Gives the next test a full instruction budget.
+function M:reset_limits()
+    if self._limits then
+        self._limits.reset()
+    end
+end
+
 return M
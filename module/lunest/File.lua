@@ -83,6 +83,10 @@ else
         if not line then
             return
         end
+        -- pipes can't seek, but reading them waits for a line feed
+        if not self[1]:seek("cur") then
+            return line .. "\n"
+        end
         self:seek_rel(-1)
         if self[1]:read(1) == "\n" then
             line = line .. "\n"
@@ -4,6 +4,7 @@
 ---@field name string
 ---@field func fun()
 ---@field source string
+---@field line_range lunest.LineRange?
 ---@field parent lunest.Group?
 ---@field children (lunest.Test | lunest.Group)[]
 local M = {}
@@ -22,12 +23,24 @@ end
 ---@private
 M.__index = M
 
+--- Returns the lines where `func` is defined if it's defined in `source`.
+---@param func function
+---@param source string
+---@return lunest.LineRange?
+function M.func_line_range(func, source)
+    local info = debug.getinfo(func, "S")
+    if info.source ~= "@" .. source or info.linedefined <= 0 then
+        return nil
+    end
+    return { first = info.linedefined, last = info.lastlinedefined }
+end
+
 ---@param cx lunest.Context
 ---@param name string
 ---@param path string
 function M.run_file(cx, name, path)
     local func = module.isolated(function()
-        assert(loadfile(path))(module.name(cx:root_dir(), path))
+        assert(loadfile(path))(module.name(cx:cwd(), path))
     end)
     local self = M.new(cx, name, path, func)
     if self then
@@ -54,6 +67,7 @@ function M.new(cx, name, source, func)
     self.name = name
     self.func = func
     self.source = source
+    self.line_range = M.func_line_range(func, source)
     self.parent = current
     self.children = {}
     return self
@@ -1,6 +1,6 @@
 ---@class lunest.Process
----@field private input lunest.File
----@field private output lunest.File
+---@field private input lunest.File | lunest.Channel
+---@field private output lunest.File | lunest.Channel
 ---@field private input_callbacks table<string, function[]>
 local M = {}
 ---@private
@@ -10,8 +10,11 @@ local json = require("json")
 
 local File = require("lunest.File")
 
----@param input lunest.File
----@param output lunest.File
+--- Incremented when the messages change incompatibly
+M.PROTOCOL_VERSION = 1
+
+---@param input lunest.File | lunest.Channel
+---@param output lunest.File | lunest.Channel
 ---@return self
 function M.new(input, output)
     local self = setmetatable({}, M)
@@ -46,7 +49,13 @@ function M:loop()
     local all_inputs_read = true
     while not self.input:is_closed() do
         local line = self.input:readln()
-        if line then
+        if line == "\n" and buf == "" then
+            -- written by lunest to wait for the inputs to be handled, since reading a pipe blocks
+            if not all_inputs_read then
+                all_inputs_read = true
+                self:write({ t = "AllInputsRead" })
+            end
+        elseif line then
             self:log("line: %s", line)
             all_inputs_read = false
             if line:sub(#line) == "\n" then
@@ -105,12 +114,18 @@ end
 
 ---@param id string
 ---@param title string[]
-function M:send_test_info(id, title)
+---@param source string
+---@param line_ranges lunest.LineRange[] lines of the enclosing groups and the test
+---@param has_line_range boolean whether the last one of `line_ranges` is the lines of the test
+function M:send_test_info(id, title, source, line_ranges, has_line_range)
     return self:write({
         t = "TestInfo",
         c = {
             id = id,
             title = title,
+            source = source,
+            line_ranges = line_ranges,
+            has_line_range = has_line_range,
         },
     })
 end
@@ -125,12 +140,26 @@ end
 
 ---@param title string[]
 ---@param err lunest.TestError?
-function M:notify_test_finished(title, err)
+---@param duration number CPU time in seconds
+function M:notify_test_finished(title, err, duration)
     return self:write({
         t = "TestFinished",
         c = {
             title = title,
             error = err,
+            duration = duration,
+        },
+    })
+end
+
+--- Sends the first message, which tells lunest what this runner supports.
+function M:send_hello()
+    return self:write({
+        t = "Hello",
+        c = {
+            protocol = M.PROTOCOL_VERSION,
+            lua_version = _G.jit and _G.jit.version or _VERSION,
+            features = { "timing", "flush" },
         },
     })
 end
@@ -144,6 +173,9 @@ end
 --- enum content
 ---@class lunest.Input.Initialize
 ---@field root_dir string
+---@field cwd string
+---@field path string[]
+---@field cpath string[]
 ---@field target_files { name: string, path: string }[]
 ---@field term_width integer
 --- enum content
@@ -162,10 +194,14 @@ end
 ---| { t: "TestFinished", c: lunest.Output.TestFinished }
 ---| { t: "AllInputsRead", c: nil }
 ---| { t: "Log", c: string }
+---| { t: "Hello", c: lunest.Output.Hello }
 --- enum content
 ---@class lunest.Output.TestInfo
 ---@field id string
 ---@field title string[]
+---@field source string
+---@field line_ranges lunest.LineRange[]
+---@field has_line_range boolean
 --- enum content
 ---@class lunest.Output.TestStarted
 ---@field title string[]
@@ -173,6 +209,21 @@ end
 ---@class lunest.Output.TestFinished
 ---@field title string[]
 ---@field error lunest.TestError?
+---@field duration number
+--- enum content
+---@class lunest.Output.Hello
+---@field protocol integer
+---@field lua_version string
+---@field features lunest.Feature[]
+--- enum
+---@alias lunest.Feature
+---| "timing" `duration` of `TestFinished`
+---| "flush" empty input lines answered with `AllInputsRead`
+
+--- struct
+---@class lunest.LineRange
+---@field first integer
+---@field last integer
 
 --- struct
 ---@class lunest.TestError
@@ -4,6 +4,7 @@
 ---@field package name string
 ---@field package func fun()
 ---@field package source string
+---@field package line_range lunest.LineRange?
 ---@field package parent lunest.Group
 local M = {}
 
@@ -46,6 +47,7 @@ function M.new(cx, name, source, func)
     self.name = name
     self.func = func
     self.source = source
+    self.line_range = Group.func_line_range(func, source)
     self.parent = parent
     return self
 end
@@ -136,17 +138,29 @@ function M:run()
     local title = self:get_title()
     local mode = self.cx:test_mode()
     if mode == "SendInfo" then
-        self.cx:process():send_test_info(self.id, title)
+        local process = self.cx:process()
+        process:send_test_info(
+            self.id,
+            title,
+            self.source,
+            self:get_line_ranges(),
+            self.line_range ~= nil
+        )
     elseif mode == "Run" then
         self.cx:process():notify_test_started(title)
         assert(not current)
         current = self
+        self.cx:reset_limits()
+        -- CPU time of the whole process, which includes the time of lunest itself and of the
+        -- other profiles if the runner runs in lunest's process
+        local start = os.clock()
         local success, err = xpcall(test_runner(self.func), handle_error)
+        local duration = os.clock() - start
         current = nil
         if success then
             err = nil
         end
-        self.cx:process():notify_test_finished(title, err)
+        self.cx:process():notify_test_finished(title, err, duration)
     end
 end
 
@@ -161,4 +175,17 @@ function M:get_title()
     return title
 end
 
+---@return lunest.LineRange[]
+function M:get_line_ranges()
+    local ranges = {}
+    local node = self ---@type (lunest.Test | lunest.Group)?
+    repeat
+        if node.line_range then
+            table.insert(ranges, 1, node.line_range)
+        end
+        node = node.parent
+    until not node
+    return ranges
+end
+
 return M
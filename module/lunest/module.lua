@@ -60,20 +60,22 @@ end)
 ---@param path string
 ---@return string?
 local function name(file, cwd, path)
-    local templates = {}
+    -- the most specific template, so that e.g. `./?/init.lua` wins over `./?.lua`
+    local result, matched = nil, -1 ---@type string?, integer
     for t in path:gmatch("[^;]+") do
-        table.insert(templates, 1, normalize_path(t, cwd))
-    end
-
-    for _, template in ipairs(templates) do
-        local prefix, suffix = template:match("([^?]*)?(.*)") ---@type string, string
+        local prefix, suffix = normalize_path(t, cwd):match("([^?]*)?(.*)") ---@type string, string
         if
-            file:find(prefix, 1, true) == 1
-            and select(2, file:find(suffix, 1, true)) == file:len()
+            prefix
+            and prefix:len() + suffix:len() > matched
+            and file:len() > prefix:len() + suffix:len()
+            and file:sub(1, prefix:len()) == prefix
+            and file:sub(file:len() - suffix:len() + 1) == suffix
         then
-            return (file:sub(prefix:len() + 1, file:len() - suffix:len()):gsub("[/\\]", "."))
+            matched = prefix:len() + suffix:len()
+            result = (file:sub(prefix:len() + 1, file:len() - suffix:len()):gsub("[/\\]", "."))
         end
     end
+    return result
 end
 
 test.test("name", function()
@@ -82,13 +84,39 @@ test.test("name", function()
     assert(name("/cwd/foo/bar.lua", r, t) == "foo.bar")
     assert(name("/cwd/foo/bar/init.lua", r, t) == "foo.bar")
     assert(name("/share/lua/foo/init.lua", r, t) == "foo.init")
+    assert(name("/cwd/lib/foo.lua", r, "./?.lua;./lib/?.lua") == "foo")
+end)
+
+---@param path string
+---@param templates string[]
+---@return string
+function M.prepend_path(path, templates)
+    if #templates == 0 then
+        return path
+    end
+    return table.concat(templates, ";") .. ";" .. path
+end
+
+test.test("prepend_path", function()
+    assert(M.prepend_path("./?.lua", {}) == "./?.lua")
+    assert(M.prepend_path("./?.lua", { "/a/?.lua", "/b/?.lua" }) == "/a/?.lua;/b/?.lua;./?.lua")
 end)
 
----@param root_dir string
+---@param cwd string
 ---@param file string
 ---@return string?
-function M.name(root_dir, file)
-    return name(file, root_dir, package.path)
+function M.name(cwd, file)
+    -- `package` of the bundle is a copy, which doesn't have the `path` of the profile
+    return name(file, cwd, _G.package.path)
 end
 
+test.test("name with configured path", function()
+    local path = _G.package.path
+    _G.package.path = M.prepend_path(path, { "/lib/?.lua" })
+    local ok, result = pcall(M.name, "/cwd", "/lib/foo/bar.lua")
+    _G.package.path = path
+    assert(ok, result)
+    assert(result == "foo.bar")
+end)
+
 return M